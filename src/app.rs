@@ -1,7 +1,7 @@
 use crate::{
     config::Config,
     log,
-    ui::{create_error_overlay, LauncherWindow},
+    ui::{show_config_error, LauncherWindow},
 };
 use gtk4::{
     glib::{self, ControlFlow},
@@ -72,24 +72,7 @@ impl App {
 
                             if let Some(main_box) = window.first_child() {
                                 if let Some(main_box) = main_box.downcast_ref::<gtk4::Box>() {
-                                    if let Some(first_child) = main_box.first_child() {
-                                        if first_child
-                                            .css_classes()
-                                            .iter()
-                                            .any(|class| class == "error-overlay")
-                                        {
-                                            main_box.remove(&first_child);
-                                        }
-                                    }
-                                }
-                            }
-
-                            if let Some(error) = error {
-                                if let Some(main_box) = window.first_child() {
-                                    if let Some(main_box) = main_box.downcast_ref::<gtk4::Box>() {
-                                        let error_overlay = create_error_overlay(&error);
-                                        main_box.prepend(&error_overlay);
-                                    }
+                                    show_config_error(main_box, error.as_ref());
                                 }
                             }
 
@@ -111,6 +94,9 @@ impl App {
                 "Loading applications ({:.3}ms)",
                 load_start.elapsed().as_secs_f64() * 1000.0
             );
+
+            crate::launcher::watch_applications();
+            crate::binaries::index_binaries();
         }
 
         Self {
@@ -0,0 +1,139 @@
+use crate::{exec, launcher, log};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use once_cell::sync::Lazy;
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    path::PathBuf,
+    sync::mpsc::channel,
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::sync::RwLock;
+
+const PATH_WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Debug)]
+pub struct PathBinary {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+pub static BINARY_INDEX: Lazy<RwLock<Vec<PathBinary>>> = Lazy::new(|| RwLock::new(Vec::new()));
+
+fn get_path_dirs() -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+
+    env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).collect::<Vec<_>>())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|dir| dir.is_absolute() && seen.insert(dir.clone()))
+        .collect()
+}
+
+// Earlier PATH entries shadow later ones, just like the shell resolves them.
+fn scan_binaries(dirs: &[PathBuf]) -> Vec<PathBinary> {
+    let mut seen = HashSet::new();
+    let mut binaries = Vec::new();
+
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+
+        let mut dir_binaries: Vec<PathBinary> = entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let path = entry.path();
+                (!name.starts_with('.') && exec::is_executable(&path))
+                    .then_some(PathBinary { name, path })
+            })
+            .collect();
+        dir_binaries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        binaries.extend(
+            dir_binaries
+                .into_iter()
+                .filter(|binary| seen.insert(binary.name.clone())),
+        );
+    }
+
+    binaries
+}
+
+fn refresh_index(dirs: &[PathBuf]) {
+    let start = Instant::now();
+    let binaries = scan_binaries(dirs);
+    log!(
+        "Indexed {} executables from PATH ({:.3}ms)",
+        binaries.len(),
+        start.elapsed().as_secs_f64() * 1000.0
+    );
+    *BINARY_INDEX.blocking_write() = binaries;
+}
+
+pub fn index_binaries() {
+    let dirs = get_path_dirs();
+
+    thread::spawn(move || {
+        refresh_index(&dirs);
+
+        let (tx, rx) = channel();
+        let mut watcher = match RecommendedWatcher::new(tx, notify::Config::default()) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                log!("Failed to create PATH watcher: {}", e);
+                return;
+            }
+        };
+
+        let mut watched = HashMap::new();
+        launcher::sync_watches(
+            &mut watcher,
+            &dirs,
+            RecursiveMode::NonRecursive,
+            &mut watched,
+        );
+
+        loop {
+            let mut changed = HashSet::new();
+
+            match rx.recv() {
+                Ok(Ok(event)) => launcher::collect_changed_paths(event, &mut changed),
+                Ok(Err(e)) => {
+                    log!("PATH watch error: {:?}", e);
+                    continue;
+                }
+                Err(_) => break,
+            }
+
+            while let Ok(result) = rx.recv_timeout(PATH_WATCH_DEBOUNCE) {
+                if let Ok(event) = result {
+                    launcher::collect_changed_paths(event, &mut changed);
+                }
+            }
+
+            // Events from the parent of a missing directory only matter once
+            // the directory itself shows up.
+            let appeared = launcher::sync_watches(
+                &mut watcher,
+                &dirs,
+                RecursiveMode::NonRecursive,
+                &mut watched,
+            );
+            if !appeared.is_empty()
+                || changed
+                    .iter()
+                    .any(|path| dirs.iter().any(|dir| path.starts_with(dir)))
+            {
+                refresh_index(&dirs);
+            }
+        }
+    });
+}
+
+pub fn find_binary<'a>(binaries: &'a [PathBinary], name: &str) -> Option<&'a PathBinary> {
+    binaries.iter().find(|binary| binary.name == name)
+}
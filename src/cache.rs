@@ -0,0 +1,128 @@
+use crate::{config, launcher::DesktopFile, log};
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const INDEX_FORMAT_VERSION: u32 = 12;
+const INDEX_FILE_NAME: &str = "desktop-index.bin";
+const INDEX_SIZE_LIMIT: u64 = 64 * 1024 * 1024;
+
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+pub struct DirRecord {
+    pub mtime: SystemTime,
+    pub children: Vec<(String, bool)>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FileRecord {
+    pub mtime: SystemTime,
+    pub desktop_id: String,
+    pub desktop_file: DesktopFile,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct IndexCache {
+    version: String,
+    context: String,
+    pub dirs: HashMap<PathBuf, DirRecord>,
+    pub files: HashMap<PathBuf, FileRecord>,
+}
+
+fn get_cache_dir() -> PathBuf {
+    config::xdg_dir("XDG_CACHE_HOME", "~/.cache").join("hyprlauncher")
+}
+
+fn index_options() -> impl Options {
+    bincode::DefaultOptions::new().with_limit(INDEX_SIZE_LIMIT)
+}
+
+fn index_version() -> String {
+    format!("{}-{}", env!("CARGO_PKG_VERSION"), INDEX_FORMAT_VERSION)
+}
+
+// Parsed entries depend on the locale and desktop environment, so a cache
+// written under a different session must not be reused.
+fn index_context() -> String {
+    [
+        "LC_ALL",
+        "LC_MESSAGES",
+        "LANG",
+        "LANGUAGE",
+        "XDG_CURRENT_DESKTOP",
+    ]
+    .iter()
+    .map(|var| env::var(var).unwrap_or_default())
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+pub fn modified(path: &std::path::Path) -> SystemTime {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .unwrap_or(UNIX_EPOCH)
+}
+
+impl IndexCache {
+    pub fn new(dirs: HashMap<PathBuf, DirRecord>, files: HashMap<PathBuf, FileRecord>) -> Self {
+        Self {
+            version: index_version(),
+            context: index_context(),
+            dirs,
+            files,
+        }
+    }
+
+    pub fn load() -> Self {
+        let path = get_cache_dir().join(INDEX_FILE_NAME);
+        let Ok(contents) = fs::read(&path) else {
+            log!("No desktop index cache found at {:?}", path);
+            return Self::default();
+        };
+
+        match index_options().deserialize::<IndexCache>(&contents) {
+            Ok(cache) if cache.version == index_version() && cache.context == index_context() => {
+                log!(
+                    "Loaded desktop index cache with {} files",
+                    cache.files.len()
+                );
+                cache
+            }
+            Ok(_) => {
+                log!("Desktop index cache is stale, rescanning all entries");
+                Self::default()
+            }
+            Err(e) => {
+                log!(
+                    "Desktop index cache is corrupt ({}), rescanning all entries",
+                    e
+                );
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let dir = get_cache_dir();
+        let path = dir.join(INDEX_FILE_NAME);
+        let tmp_path = dir.join(format!("{}.{}.tmp", INDEX_FILE_NAME, std::process::id()));
+
+        let result = fs::create_dir_all(&dir)
+            .and_then(|_| {
+                index_options()
+                    .serialize(self)
+                    .map_err(std::io::Error::other)
+            })
+            .and_then(|contents| fs::write(&tmp_path, contents))
+            .and_then(|_| fs::rename(&tmp_path, &path));
+
+        if let Err(e) = result {
+            log!("Failed to write desktop index cache: {}", e);
+            let _ = fs::remove_file(&tmp_path);
+        }
+    }
+}
@@ -3,6 +3,7 @@ use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     env, fs,
     path::PathBuf,
     sync::{
@@ -36,6 +37,16 @@ static CONFIG_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
     default_config_path
 });
 
+// Base directories from the XDG spec. Relative values are invalid and fall
+// back to the default, like an unset variable.
+pub fn xdg_dir(var: &str, default: &str) -> PathBuf {
+    env::var(var)
+        .ok()
+        .filter(|dir| dir.starts_with('/'))
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(shellexpand::tilde(default).to_string()))
+}
+
 pub static LOGGING_ENABLED: AtomicBool = AtomicBool::new(false);
 
 static CURRENT_CONFIG_ERROR: Lazy<Mutex<Option<ConfigError>>> = Lazy::new(|| Mutex::new(None));
@@ -155,6 +166,10 @@ pub struct Config {
     pub calculator: Calculator,
     pub dmenu: Dmenu,
     pub web_search: WebSearch,
+    #[serde(default)]
+    pub launch: Launch,
+    #[serde(default)]
+    pub ranking: Ranking,
 }
 
 #[allow(non_camel_case_types)]
@@ -305,6 +320,65 @@ pub struct WebSearch {
     pub prefixes: Vec<SearchPrefix>,
 }
 
+#[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+#[serde(default)]
+pub struct LaunchRule {
+    pub desktop_id: Option<String>,
+    pub category: Option<String>,
+    pub regex: Option<String>,
+    pub prepend: Option<String>,
+    pub env: BTreeMap<String, String>,
+    pub wrapper: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Launch {
+    pub working_directory: String,
+    pub terminal: String,
+    pub wrapper: String,
+    pub log_output: bool,
+    pub rules: Vec<LaunchRule>,
+}
+
+impl Default for Launch {
+    fn default() -> Self {
+        Self {
+            working_directory: String::from("~"),
+            terminal: String::new(),
+            wrapper: String::new(),
+            log_output: false,
+            rules: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Ranking {
+    pub half_life_hours: f64,
+    pub frequency_weight: f64,
+    pub recency_weight: f64,
+    pub query_weight: f64,
+    pub query_history_size: usize,
+    pub time_of_day_weight: f64,
+    pub weekday_weight: f64,
+}
+
+impl Default for Ranking {
+    fn default() -> Self {
+        Self {
+            half_life_hours: 72.0,
+            frequency_weight: 800.0,
+            recency_weight: 4000.0,
+            query_weight: 5000.0,
+            query_history_size: 500,
+            time_of_day_weight: 6000.0,
+            weekday_weight: 2000.0,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ConfigError {
     pub line: usize,
@@ -0,0 +1,119 @@
+use gtk4::{gio, glib, prelude::*};
+use std::collections::HashMap;
+
+const APPLICATION_INTERFACE: &str = "org.freedesktop.Application";
+const ACTIVATION_TIMEOUT_MS: i32 = 5000;
+
+pub fn bus_name(desktop_id: &str) -> Option<&str> {
+    let name = desktop_id.strip_suffix(".desktop")?;
+    let elements: Vec<&str> = name.split('.').collect();
+
+    let valid = elements.len() > 1
+        && name.len() <= 255
+        && elements.iter().all(|element| {
+            !element.is_empty()
+                && !element.starts_with(|c: char| c.is_ascii_digit())
+                && element
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        });
+
+    valid.then_some(name)
+}
+
+fn object_path(bus_name: &str) -> String {
+    format!("/{}", bus_name.replace('.', "/").replace('-', "_"))
+}
+
+// Services are often started on demand and can take seconds to come up,
+// so the call must not block the UI thread.
+pub fn activate(
+    desktop_id: &str,
+    action: Option<&str>,
+    uris: &[String],
+    platform_data: HashMap<String, glib::Variant>,
+    callback: impl FnOnce(Result<(), glib::Error>) + 'static,
+) {
+    let Some(bus_name) = bus_name(desktop_id) else {
+        callback(Err(glib::Error::new(
+            gio::IOErrorEnum::InvalidArgument,
+            &format!("{} is not a valid D-Bus name", desktop_id),
+        )));
+        return;
+    };
+
+    let (method, parameters) = match action {
+        Some(action) => (
+            "ActivateAction",
+            (action, Vec::<glib::Variant>::new(), platform_data).to_variant(),
+        ),
+        None if !uris.is_empty() => ("Open", (uris.to_vec(), platform_data).to_variant()),
+        None => ("Activate", (platform_data,).to_variant()),
+    };
+
+    let bus_name = bus_name.to_string();
+    gio::bus_get(
+        gio::BusType::Session,
+        None::<&gio::Cancellable>,
+        move |connection| {
+            let connection = match connection {
+                Ok(connection) => connection,
+                Err(e) => return callback(Err(e)),
+            };
+
+            connection.call(
+                Some(&bus_name),
+                &object_path(&bus_name),
+                APPLICATION_INTERFACE,
+                method,
+                Some(&parameters),
+                None,
+                gio::DBusCallFlags::NONE,
+                ACTIVATION_TIMEOUT_MS,
+                None::<&gio::Cancellable>,
+                move |result| callback(result.map(|_| ())),
+            );
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bus_name_strips_desktop_suffix() {
+        assert_eq!(
+            bus_name("org.gnome.Nautilus.desktop"),
+            Some("org.gnome.Nautilus")
+        );
+        assert_eq!(
+            bus_name("org.gnome.Nautilus-Previewer.desktop"),
+            Some("org.gnome.Nautilus-Previewer")
+        );
+    }
+
+    #[test]
+    fn bus_name_rejects_invalid_ids() {
+        for id in [
+            "org.gnome.Nautilus",
+            "firefox.desktop",
+            ".org.gnome.desktop",
+            "org..gnome.desktop",
+            "org.7zip.App.desktop",
+            "org.gnome.Nautilus Previewer.desktop",
+            "org.gnome.Über.desktop",
+        ] {
+            assert_eq!(bus_name(id), None, "{}", id);
+        }
+
+        let too_long = format!("org.{}.desktop", "a".repeat(252));
+        assert_eq!(bus_name(&too_long), None);
+    }
+
+    #[test]
+    fn object_path_maps_dots_and_dashes() {
+        assert_eq!(object_path("org.gnome.Nautilus"), "/org/gnome/Nautilus");
+        assert_eq!(object_path("org.example.My-App_2"), "/org/example/My_App_2");
+    }
+}
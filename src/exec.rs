@@ -0,0 +1,764 @@
+use crate::{
+    config::{self, Launch, LaunchRule},
+    launcher::AppEntry,
+    log, terminal,
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom},
+    os::unix::{
+        fs::{OpenOptionsExt, PermissionsExt},
+        process::CommandExt,
+    },
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+const COMMAND_PLACEHOLDER: &str = "{cmd}";
+const GENERIC_LAUNCHERS: &[&str] = &[
+    "sh",
+    "bash",
+    "dash",
+    "zsh",
+    "fish",
+    "snap",
+    "xdg-open",
+    "gtk-launch",
+    "gio",
+];
+const STDERR_TAIL_LINES: usize = 8;
+const STDERR_TAIL_BYTES: u64 = 16 * 1024;
+pub const LAUNCH_GRACE_PERIOD: Duration = Duration::from_millis(1500);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ExecArg {
+    Literal(String),
+    Placeholder {
+        code: char,
+        prefix: String,
+        suffix: String,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Activation {
+    Spawn(Vec<ExecArg>),
+    Shell(String),
+    CopyText(String),
+    OpenUri(String),
+}
+
+impl Activation {
+    pub fn to_argv(&self) -> Vec<String> {
+        match self {
+            Activation::Spawn(args) => expand_exec(args, &[]),
+            Activation::Shell(command) => {
+                vec![String::from("sh"), String::from("-c"), command.clone()]
+            }
+            Activation::CopyText(_) => Vec::new(),
+            Activation::OpenUri(uri) => vec![String::from("xdg-open"), uri.clone()],
+        }
+    }
+
+    pub fn to_command_line(&self) -> String {
+        match self {
+            Activation::Shell(command) | Activation::CopyText(command) => command.clone(),
+            _ => argv_to_string(&self.to_argv()),
+        }
+    }
+}
+
+pub struct ExecContext<'a> {
+    pub icon: Option<&'a str>,
+    pub name: &'a str,
+    pub location: &'a str,
+}
+
+fn unescape_string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('s') => result.push(' '),
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('\\') => result.push('\\'),
+            Some(other) => {
+                result.push('\\');
+                result.push(other);
+            }
+            None => result.push('\\'),
+        }
+    }
+
+    result
+}
+
+struct ArgBuilder {
+    args: Vec<ExecArg>,
+    current: String,
+    placeholder: Option<(char, String)>,
+    started: bool,
+}
+
+impl ArgBuilder {
+    fn push_str(&mut self, s: &str) {
+        self.current.push_str(s);
+        self.started = true;
+    }
+
+    fn push(&mut self, c: char) {
+        self.current.push(c);
+        self.started = true;
+    }
+
+    fn set_placeholder(&mut self, code: char) {
+        if self.placeholder.is_some() {
+            return;
+        }
+        self.placeholder = Some((code, std::mem::take(&mut self.current)));
+        self.started = true;
+    }
+
+    fn finish(&mut self) {
+        if !self.started {
+            return;
+        }
+
+        let current = std::mem::take(&mut self.current);
+        match self.placeholder.take() {
+            Some((code, prefix)) => self.args.push(ExecArg::Placeholder {
+                code,
+                prefix,
+                suffix: current,
+            }),
+            None => self.args.push(ExecArg::Literal(current)),
+        }
+        self.started = false;
+    }
+}
+
+pub fn parse_exec(value: &str, ctx: &ExecContext) -> Option<Vec<ExecArg>> {
+    let value = unescape_string(value);
+    let mut builder = ArgBuilder {
+        args: Vec::new(),
+        current: String::new(),
+        placeholder: None,
+        started: false,
+    };
+    let mut chars = value.chars().peekable();
+    let mut in_quotes = false;
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' => in_quotes = false,
+                '\\' => match chars.peek() {
+                    Some(&next @ ('"' | '`' | '$' | '\\')) => {
+                        builder.push(next);
+                        chars.next();
+                    }
+                    _ => builder.push('\\'),
+                },
+                _ => builder.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            ' ' | '\t' | '\n' => builder.finish(),
+            '"' => {
+                in_quotes = true;
+                builder.started = true;
+            }
+            '\\' => {
+                if let Some(next) = chars.next() {
+                    builder.push(next);
+                }
+            }
+            '%' => match chars.next() {
+                Some('%') => builder.push('%'),
+                Some(code @ ('f' | 'F' | 'u' | 'U')) => builder.set_placeholder(code),
+                Some('i') => {
+                    if let Some(icon) = ctx.icon.filter(|icon| !icon.is_empty()) {
+                        if builder.started {
+                            builder.push_str(icon);
+                        } else {
+                            builder.push_str("--icon");
+                            builder.finish();
+                            builder.push_str(icon);
+                        }
+                    }
+                }
+                Some('c') => builder.push_str(ctx.name),
+                Some('k') => builder.push_str(ctx.location),
+                _ => {}
+            },
+            _ => builder.push(c),
+        }
+    }
+
+    if in_quotes {
+        return None;
+    }
+
+    builder.finish();
+    Some(builder.args)
+}
+
+pub fn expand_exec(args: &[ExecArg], uris: &[String]) -> Vec<String> {
+    let mut argv = Vec::with_capacity(args.len() + uris.len());
+
+    for arg in args {
+        match arg {
+            ExecArg::Literal(value) => argv.push(value.clone()),
+            ExecArg::Placeholder {
+                code,
+                prefix,
+                suffix,
+            } => match code {
+                'F' | 'U' => argv.extend(
+                    uris.iter()
+                        .map(|uri| format!("{}{}{}", prefix, uri, suffix)),
+                ),
+                _ => {
+                    if let Some(uri) = uris.first() {
+                        argv.push(format!("{}{}{}", prefix, uri, suffix));
+                    }
+                }
+            },
+        }
+    }
+
+    argv
+}
+
+pub fn shell_quote(arg: &str) -> String {
+    let is_safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c));
+
+    if is_safe {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+pub fn argv_to_string(argv: &[String]) -> String {
+    argv.iter()
+        .map(|arg| shell_quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn exec_to_string(args: &[ExecArg]) -> String {
+    argv_to_string(&expand_exec(args, &[]))
+}
+
+fn file_name(path: &str) -> Option<&str> {
+    Path::new(path).file_name()?.to_str()
+}
+
+// Leaves the name empty for programs that only start something else, since
+// every entry using them would otherwise match their name.
+pub fn executable_name(args: &[ExecArg]) -> Option<String> {
+    let mut literals = args.iter().map_while(|arg| match arg {
+        ExecArg::Literal(value) => Some(value.as_str()),
+        ExecArg::Placeholder { .. } => None,
+    });
+
+    let mut program = file_name(literals.next()?)?;
+    if program == "env" {
+        program = file_name(literals.find(|arg| !arg.starts_with('-') && !arg.contains('='))?)?;
+    }
+
+    match program {
+        "flatpak" => literals
+            .find_map(|arg| arg.strip_prefix("--command="))
+            .and_then(file_name)
+            .map(String::from),
+        program if GENERIC_LAUNCHERS.contains(&program) => None,
+        program => Some(program.to_string()),
+    }
+}
+
+pub fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+pub fn find_executable(name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        let path = PathBuf::from(shellexpand::tilde(name).to_string());
+        return is_executable(&path).then_some(path);
+    }
+
+    env::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths)
+            .map(|dir| dir.join(name))
+            .find(|candidate| is_executable(candidate))
+    })
+}
+
+pub struct LaunchCommand {
+    pub argv: Vec<String>,
+    pub env: Vec<(String, String)>,
+    pub working_dir: Option<PathBuf>,
+    pub log_path: Option<PathBuf>,
+}
+
+fn get_state_dir() -> PathBuf {
+    config::xdg_dir("XDG_STATE_HOME", "~/.local/state").join("hyprlauncher")
+}
+
+fn log_path(app: &AppEntry, argv: &[String]) -> PathBuf {
+    let name = Some(app.desktop_id.trim_end_matches(".desktop"))
+        .filter(|id| !id.is_empty())
+        .or_else(|| {
+            argv.first()
+                .and_then(|program| Path::new(program).file_name())
+                .and_then(|name| name.to_str())
+        })
+        .unwrap_or("unknown")
+        .replace('/', "_");
+
+    get_state_dir().join(format!("{}.log", name))
+}
+
+fn open_log(path: &Path) -> Option<File> {
+    fs::create_dir_all(get_state_dir())
+        .and_then(|_| OpenOptions::new().create(true).append(true).open(path))
+        .map_err(|e| log!("Failed to open launch log {:?}: {}", path, e))
+        .ok()
+}
+
+pub struct LaunchFailure {
+    pub reason: String,
+    pub stderr: String,
+}
+
+pub struct LaunchedProcess {
+    pub pid: u32,
+    stderr: Option<(File, u64)>,
+}
+
+impl LaunchedProcess {
+    pub fn stderr_tail(&self) -> String {
+        let Some((file, offset)) = &self.stderr else {
+            return String::new();
+        };
+        let mut file = file;
+
+        let len = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        let start = (*offset).max(len.saturating_sub(STDERR_TAIL_BYTES));
+        let mut contents = Vec::new();
+        if let Err(e) = file
+            .seek(SeekFrom::Start(start))
+            .and_then(|_| file.read_to_end(&mut contents))
+        {
+            log!("Failed to read stderr of pid {}: {}", self.pid, e);
+            return String::new();
+        }
+
+        let contents = String::from_utf8_lossy(&contents);
+        let lines: Vec<&str> = contents.lines().collect();
+        lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join("\n")
+    }
+}
+
+// Picks where the child's stderr goes: its log when output is logged,
+// otherwise a nameless temporary file that disappears with its last
+// descriptor. Either way the child never holds a pipe back to us. Returns
+// the child's handle plus a reader and the offset its output starts at.
+fn stderr_capture(log_path: Option<&Path>) -> Option<(File, (File, u64))> {
+    let capture = match log_path {
+        Some(path) => open_log(path).map(|file| {
+            let reader = File::open(path)?;
+            let offset = reader.metadata()?.len();
+            Ok((file, (reader, offset)))
+        }),
+        None => Some(
+            OpenOptions::new()
+                .read(true)
+                .append(true)
+                .mode(0o600)
+                .custom_flags(libc::O_TMPFILE)
+                .open(env::temp_dir())
+                .and_then(|file| Ok((file.try_clone()?, (file, 0)))),
+        ),
+    }?;
+
+    capture
+        .map_err(|e| log!("Failed to capture launch stderr: {}", e))
+        .ok()
+}
+
+impl LaunchCommand {
+    // The child is left unreaped on purpose: the caller registers it with
+    // the main loop's child watch, which reaps it whenever it exits without
+    // tying up a thread per running application.
+    pub fn spawn(&self, startup_id: Option<&str>) -> io::Result<LaunchedProcess> {
+        let mut command = Command::new(&self.argv[0]);
+        command
+            .args(&self.argv[1..])
+            .env_remove("XDG_ACTIVATION_TOKEN")
+            .env_remove("DESKTOP_STARTUP_ID")
+            .envs(self.env.iter().cloned())
+            .stdin(Stdio::null());
+
+        if let Some(id) = startup_id {
+            command
+                .env("XDG_ACTIVATION_TOKEN", id)
+                .env("DESKTOP_STARTUP_ID", id);
+        }
+        if let Some(dir) = &self.working_dir {
+            command.current_dir(dir);
+        }
+        match self.log_path.as_deref().and_then(open_log) {
+            Some(file) => command.stdout(file),
+            None => command.stdout(Stdio::null()),
+        };
+        let (stderr_file, stderr) = stderr_capture(self.log_path.as_deref()).unzip();
+        match stderr_file {
+            Some(file) => command.stderr(file),
+            None => command.stderr(Stdio::null()),
+        };
+
+        // Start the child in its own session so it outlives the launcher and
+        // is not hit by signals sent to our process group.
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        let child = command.spawn()?;
+
+        Ok(LaunchedProcess {
+            pid: child.id(),
+            stderr,
+        })
+    }
+}
+
+fn split_template(template: &str) -> Vec<String> {
+    shlex::split(template).unwrap_or_else(|| {
+        log!("Ignoring malformed launch template: {}", template);
+        Vec::new()
+    })
+}
+
+pub fn wrap_command(template: &str, argv: Vec<String>) -> Vec<String> {
+    let template = split_template(template);
+    if template.is_empty() {
+        return argv;
+    }
+
+    if !template.iter().any(|arg| arg.contains(COMMAND_PLACEHOLDER)) {
+        return template.into_iter().chain(argv).collect();
+    }
+
+    // A standalone {cmd} becomes the separate arguments, while one embedded
+    // in a larger argument, like `sh -c "exec {cmd}"`, becomes the quoted
+    // command line.
+    template
+        .into_iter()
+        .flat_map(|arg| {
+            if arg == COMMAND_PLACEHOLDER {
+                argv.clone()
+            } else {
+                vec![arg.replace(COMMAND_PLACEHOLDER, &argv_to_string(&argv))]
+            }
+        })
+        .collect()
+}
+
+fn rule_matches(rule: &LaunchRule, app: &AppEntry) -> bool {
+    let id_matches = rule.desktop_id.as_ref().is_none_or(|id| {
+        app.desktop_id == *id || app.desktop_id.trim_end_matches(".desktop") == id
+    });
+    let category_matches = rule.category.as_ref().is_none_or(|category| {
+        app.categories
+            .iter()
+            .any(|c| c.eq_ignore_ascii_case(category))
+    });
+    let regex_matches = rule
+        .regex
+        .as_ref()
+        .is_none_or(|pattern| match Regex::new(pattern) {
+            Ok(regex) => [&app.desktop_id, &app.name, &app.exec]
+                .iter()
+                .any(|value| regex.is_match(value)),
+            Err(e) => {
+                log!("Ignoring launch rule with invalid regex {}: {}", pattern, e);
+                false
+            }
+        });
+
+    id_matches && category_matches && regex_matches
+}
+
+fn working_directory(app: &AppEntry, launch: &Launch) -> Option<PathBuf> {
+    let entry_dir = app
+        .working_dir
+        .as_deref()
+        .map(|dir| PathBuf::from(shellexpand::tilde(dir).to_string()))
+        .filter(|dir| {
+            let exists = dir.is_dir();
+            if !exists {
+                log!("Working directory {:?} of {} does not exist", dir, app.name);
+            }
+            exists
+        });
+
+    entry_dir.or_else(|| {
+        let dir = Some(&launch.working_directory).filter(|dir| !dir.is_empty())?;
+        let expanded = match shellexpand::full(dir) {
+            Ok(expanded) => PathBuf::from(expanded.to_string()),
+            Err(e) => {
+                log!("Failed to expand working directory {:?}: {}", dir, e);
+                return None;
+            }
+        };
+        if !expanded.is_dir() {
+            log!("Working directory {:?} does not exist", expanded);
+            return None;
+        }
+        Some(expanded)
+    })
+}
+
+pub fn build_command(app: &AppEntry, launch: &Launch) -> Option<LaunchCommand> {
+    let mut argv = app.activation.to_argv();
+
+    if argv.is_empty() {
+        return None;
+    }
+
+    let mut env = Vec::new();
+    let mut wrapper = launch.wrapper.as_str();
+    for rule in launch.rules.iter().filter(|rule| rule_matches(rule, app)) {
+        if let Some(prepend) = &rule.prepend {
+            argv = split_template(prepend).into_iter().chain(argv).collect();
+        }
+        env.extend(rule.env.iter().map(|(k, v)| (k.clone(), v.clone())));
+        if let Some(rule_wrapper) = &rule.wrapper {
+            wrapper = rule_wrapper;
+        }
+    }
+
+    if app.terminal {
+        argv = terminal::wrap_in_terminal(argv, &launch.terminal);
+    }
+
+    // Opening links, files and web searches goes through xdg-open, which
+    // hands off to an already configured application.
+    let argv = match app.activation {
+        Activation::OpenUri(_) => argv,
+        _ => wrap_command(wrapper, argv),
+    };
+    let log_path = launch.log_output.then(|| log_path(app, &argv));
+
+    Some(LaunchCommand {
+        argv,
+        env,
+        working_dir: working_directory(app, launch),
+        log_path,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CTX: ExecContext = ExecContext {
+        icon: Some("firefox"),
+        name: "Firefox",
+        location: "/usr/share/applications/firefox.desktop",
+    };
+
+    fn argv(exec: &str) -> Vec<String> {
+        expand_exec(&parse_exec(exec, &CTX).unwrap(), &[])
+    }
+
+    fn argv_with(exec: &str, uris: &[&str]) -> Vec<String> {
+        let uris: Vec<String> = uris.iter().map(|uri| uri.to_string()).collect();
+        expand_exec(&parse_exec(exec, &CTX).unwrap(), &uris)
+    }
+
+    #[test]
+    fn splits_on_whitespace() {
+        assert_eq!(argv("foo  --bar\tbaz"), ["foo", "--bar", "baz"]);
+    }
+
+    #[test]
+    fn quoted_argument_keeps_spaces() {
+        assert_eq!(
+            argv(r#""/opt/My App/app" --title "Hello World""#),
+            ["/opt/My App/app", "--title", "Hello World"]
+        );
+        assert_eq!(argv(r#"foo """#), ["foo", ""]);
+    }
+
+    #[test]
+    fn double_quote_escapes() {
+        assert_eq!(
+            argv(r#"sh -c "echo \\"hi\\" \\$HOME \\`x\\`""#),
+            ["sh", "-c", r#"echo "hi" $HOME `x`"#]
+        );
+    }
+
+    #[test]
+    fn escaped_backslash_inside_quotes() {
+        // The string unescape pass turns \\\\ into \\, and the quoting rules
+        // then turn \\ into a single backslash.
+        assert_eq!(argv(r#"foo "a\\\\b""#), ["foo", r"a\b"]);
+    }
+
+    #[test]
+    fn string_escapes_are_unescaped_first() {
+        assert_eq!(argv(r#"foo "a\sb""#), ["foo", "a b"]);
+        assert_eq!(argv(r"foo a\sb"), ["foo", "a", "b"]);
+    }
+
+    #[test]
+    fn unterminated_quote_is_rejected() {
+        assert!(parse_exec(r#"foo "bar"#, &CTX).is_none());
+    }
+
+    #[test]
+    fn percent_percent_is_a_literal_percent() {
+        assert_eq!(argv("printf 100%%"), ["printf", "100%"]);
+    }
+
+    #[test]
+    fn icon_expands_to_flag_and_value() {
+        assert_eq!(argv("foo %i"), ["foo", "--icon", "firefox"]);
+    }
+
+    #[test]
+    fn icon_expands_to_nothing_without_icon() {
+        for icon in [None, Some("")] {
+            let ctx = ExecContext { icon, ..CTX };
+            let args = parse_exec("foo %i bar", &ctx).unwrap();
+            assert_eq!(expand_exec(&args, &[]), ["foo", "bar"]);
+        }
+    }
+
+    #[test]
+    fn name_and_location_codes() {
+        assert_eq!(
+            argv("foo --class=%c %k"),
+            [
+                "foo",
+                "--class=Firefox",
+                "/usr/share/applications/firefox.desktop"
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_and_deprecated_codes_are_dropped() {
+        assert_eq!(argv("foo %d %D %n %N %v %m bar"), ["foo", "bar"]);
+    }
+
+    #[test]
+    fn single_file_placeholders_take_the_first_uri() {
+        assert_eq!(argv_with("foo %f", &["a", "b"]), ["foo", "a"]);
+        assert_eq!(argv_with("foo %u", &["a", "b"]), ["foo", "a"]);
+        assert_eq!(argv_with("foo %u", &[]), ["foo"]);
+    }
+
+    #[test]
+    fn list_placeholders_take_every_uri() {
+        assert_eq!(argv_with("foo %F", &["a", "b"]), ["foo", "a", "b"]);
+        assert_eq!(argv_with("foo %U --new", &[]), ["foo", "--new"]);
+    }
+
+    #[test]
+    fn placeholder_keeps_surrounding_text() {
+        assert_eq!(
+            argv_with("foo --file=%f.txt", &["a"]),
+            ["foo", "--file=a.txt"]
+        );
+    }
+
+    #[test]
+    fn spawned_argv_round_trips_through_shell_quoting() {
+        let argv = argv(r#""/opt/My App/app" it's"#);
+        assert_eq!(argv_to_string(&argv), r#"'/opt/My App/app' 'it'\''s'"#);
+    }
+
+    fn executable(exec: &str) -> Option<String> {
+        executable_name(&parse_exec(exec, &CTX).unwrap())
+    }
+
+    #[test]
+    fn executable_is_the_program_basename() {
+        assert_eq!(
+            executable("/usr/bin/firefox %u").as_deref(),
+            Some("firefox")
+        );
+        assert_eq!(
+            executable("env GDK_BACKEND=x11 code --new-window").as_deref(),
+            Some("code")
+        );
+    }
+
+    #[test]
+    fn launchers_have_no_executable() {
+        assert_eq!(executable("flatpak run org.gnome.Maps"), None);
+        assert_eq!(executable("sh -c \"foo | bar\""), None);
+        assert_eq!(executable("env FOO=1 bash -c foo"), None);
+        assert_eq!(
+            executable("flatpak run --command=telegram-desktop org.telegram.desktop").as_deref(),
+            Some("telegram-desktop")
+        );
+    }
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn wrapper_splices_standalone_placeholder() {
+        assert_eq!(
+            wrap_command("uwsm app -- {cmd}", strings(&["foo", "a b"])),
+            ["uwsm", "app", "--", "foo", "a b"]
+        );
+    }
+
+    #[test]
+    fn wrapper_appends_without_placeholder() {
+        assert_eq!(
+            wrap_command("gamemoderun", strings(&["foo"])),
+            ["gamemoderun", "foo"]
+        );
+        assert_eq!(wrap_command("", strings(&["foo"])), ["foo"]);
+    }
+
+    #[test]
+    fn wrapper_quotes_embedded_placeholder() {
+        assert_eq!(
+            wrap_command(r#"sh -c "exec {cmd}""#, strings(&["foo", "a b"])),
+            ["sh", "-c", "exec foo 'a b'"]
+        );
+    }
+}
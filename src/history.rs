@@ -0,0 +1,542 @@
+use crate::{
+    config::{self, Ranking},
+    log,
+};
+use chrono::{DateTime, Datelike, Local, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::fd::AsRawFd,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const HISTORY_FILE: &str = "heatmap.toml";
+const HISTORY_VERSION: u32 = 2;
+const LEGACY_HISTORY_PATH: &str = "~/.local/share/hyprlauncher/heatmap.toml";
+const MAX_LAUNCH_LOG: usize = 100;
+// Acts like a few launches at other times, so that an app opened once at
+// this hour doesn't outrank one that is opened here every day.
+const TIME_CONTEXT_PRIOR: f64 = 5.0;
+
+#[derive(Serialize, Deserialize)]
+pub struct HeatmapEntry {
+    pub count: u32,
+    pub last_used: u64,
+    #[serde(default)]
+    pub launches: Vec<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct QueryEntry {
+    pub query: String,
+    pub key: String,
+    pub count: u32,
+    pub last_used: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct History {
+    version: u32,
+    pub entries: HashMap<String, HeatmapEntry>,
+    #[serde(default)]
+    pub queries: Vec<QueryEntry>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            version: HISTORY_VERSION,
+            entries: HashMap::with_capacity(100),
+            queries: Vec::new(),
+        }
+    }
+}
+
+impl History {
+    // For every entry that was picked after typing a prefix of `query`, the
+    // share of those picks that went to it. Longer, more specific prefixes
+    // are counted separately and the best share wins.
+    pub fn query_shares(&self, query: &str) -> HashMap<&str, f64> {
+        let query = normalize_query(query);
+        let mut totals: HashMap<&str, u32> = HashMap::new();
+        for pair in self
+            .queries
+            .iter()
+            .filter(|pair| query.starts_with(&pair.query))
+        {
+            *totals.entry(pair.query.as_str()).or_default() += pair.count;
+        }
+
+        let mut shares: HashMap<&str, f64> = HashMap::new();
+        for pair in &self.queries {
+            let Some(&total) = totals.get(pair.query.as_str()) else {
+                continue;
+            };
+            let share = pair.count as f64 / total.max(1) as f64;
+            let best = shares.entry(pair.key.as_str()).or_default();
+            *best = best.max(share);
+        }
+
+        shares
+    }
+}
+
+enum StoredHeatmap {
+    Current(History),
+    Legacy(HashMap<String, HeatmapEntry>),
+}
+
+// Held for the whole read-modify-write cycle so that two launches in quick
+// succession, or two launcher instances, cannot drop each other's updates.
+struct HistoryLock(File);
+
+impl HistoryLock {
+    fn acquire() -> io::Result<Self> {
+        fs::create_dir_all(get_data_dir())?;
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(lock_path())?;
+
+        Self::lock(file, libc::LOCK_EX)
+    }
+
+    // Readers share the lock, so searches don't queue up behind each other.
+    // Without a lock file nothing has been written yet and there is nothing
+    // to wait for.
+    fn acquire_shared() -> io::Result<Option<Self>> {
+        match File::open(lock_path()) {
+            Ok(file) => Self::lock(file, libc::LOCK_SH).map(Some),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn lock(file: File, operation: libc::c_int) -> io::Result<Self> {
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self(file))
+    }
+}
+
+impl Drop for HistoryLock {
+    fn drop(&mut self) {
+        unsafe { libc::flock(self.0.as_raw_fd(), libc::LOCK_UN) };
+    }
+}
+
+fn get_data_dir() -> PathBuf {
+    config::xdg_dir("XDG_DATA_HOME", "~/.local/share").join("hyprlauncher")
+}
+
+fn history_path() -> PathBuf {
+    get_data_dir().join(HISTORY_FILE)
+}
+
+fn lock_path() -> PathBuf {
+    get_data_dir().join(format!("{}.lock", HISTORY_FILE))
+}
+
+fn read_contents() -> Option<String> {
+    match fs::read_to_string(history_path()) {
+        Ok(contents) => Some(contents),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            fs::read_to_string(shellexpand::tilde(LEGACY_HISTORY_PATH).to_string()).ok()
+        }
+        Err(e) => {
+            log!("Failed to read launch history: {}", e);
+            None
+        }
+    }
+}
+
+// Returns None if the file can't be parsed in either format.
+fn read_stored() -> Option<StoredHeatmap> {
+    let Some(contents) = read_contents() else {
+        return Some(StoredHeatmap::Current(History::default()));
+    };
+
+    if let Ok(history) = toml::from_str::<History>(&contents) {
+        return Some(StoredHeatmap::Current(history));
+    }
+    toml::from_str(&contents).ok().map(StoredHeatmap::Legacy)
+}
+
+// Must be called with the exclusive lock held, since a corrupt file is
+// moved aside.
+fn read_locked(_lock: &HistoryLock) -> StoredHeatmap {
+    if let Some(stored) = read_stored() {
+        return stored;
+    }
+
+    let path = history_path();
+    let backup = path.with_extension("toml.bak");
+    match fs::rename(&path, &backup) {
+        Ok(()) => log!("Launch history is corrupt, moved it to {:?}", backup),
+        Err(e) => log!(
+            "Launch history is corrupt and could not be backed up: {}",
+            e
+        ),
+    }
+
+    StoredHeatmap::Current(History::default())
+}
+
+fn write_locked(_lock: &HistoryLock, history: &History) -> Result<(), io::Error> {
+    let contents = toml::to_string(history).map_err(io::Error::other)?;
+
+    let path = history_path();
+    let temp_path = path.with_extension("toml.tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(temp_path, path)
+}
+
+fn normalize_query(query: &str) -> String {
+    query.trim().to_lowercase()
+}
+
+pub fn load() -> History {
+    let _lock = match HistoryLock::acquire_shared() {
+        Ok(lock) => lock,
+        Err(e) => {
+            log!("Failed to lock launch history: {}", e);
+            return History::default();
+        }
+    };
+
+    match read_stored() {
+        Some(StoredHeatmap::Current(history)) => history,
+        _ => History::default(),
+    }
+}
+
+pub fn record(key: &str, query: &str, max_queries: usize) -> Result<(u32, u64), io::Error> {
+    let lock = HistoryLock::acquire()?;
+    let mut history = match read_locked(&lock) {
+        StoredHeatmap::Current(history) => history,
+        // Not migrated yet; leave it for the next application scan.
+        StoredHeatmap::Legacy(_) => {
+            return Err(io::Error::other("launch history has not been migrated"))
+        }
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let entry = history
+        .entries
+        .entry(key.to_string())
+        .or_insert(HeatmapEntry {
+            count: 0,
+            last_used: now,
+            launches: Vec::new(),
+        });
+    entry.count += 1;
+    entry.last_used = now;
+    entry.launches.push(now);
+    if entry.launches.len() > MAX_LAUNCH_LOG {
+        entry
+            .launches
+            .drain(..entry.launches.len() - MAX_LAUNCH_LOG);
+    }
+    let count = entry.count;
+
+    let query = normalize_query(query);
+    if !query.is_empty() && max_queries > 0 {
+        match history
+            .queries
+            .iter_mut()
+            .find(|pair| pair.query == query && pair.key == key)
+        {
+            Some(pair) => {
+                pair.count += 1;
+                pair.last_used = now;
+            }
+            None => history.queries.insert(
+                0,
+                QueryEntry {
+                    query,
+                    key: key.to_string(),
+                    count: 1,
+                    last_used: now,
+                },
+            ),
+        }
+    }
+    if history.queries.len() > max_queries {
+        history
+            .queries
+            .sort_by_key(|pair| std::cmp::Reverse(pair.last_used));
+        history.queries.truncate(max_queries);
+    }
+
+    write_locked(&lock, &history)?;
+
+    Ok((count, now))
+}
+
+pub fn clear_queries() -> Result<(), io::Error> {
+    let lock = HistoryLock::acquire()?;
+    let mut history = match read_locked(&lock) {
+        StoredHeatmap::Current(history) => history,
+        StoredHeatmap::Legacy(_) => return Ok(()),
+    };
+
+    history.queries.clear();
+    write_locked(&lock, &history)
+}
+
+pub fn migrate(
+    rekey: impl FnOnce(HashMap<String, HeatmapEntry>) -> HashMap<String, HeatmapEntry>,
+) -> HashMap<String, HeatmapEntry> {
+    let lock = match HistoryLock::acquire() {
+        Ok(lock) => lock,
+        Err(e) => {
+            log!("Failed to lock launch history: {}", e);
+            return HashMap::new();
+        }
+    };
+
+    let legacy = match read_locked(&lock) {
+        StoredHeatmap::Current(history) => return history.entries,
+        StoredHeatmap::Legacy(entries) => entries,
+    };
+
+    let history = History {
+        entries: rekey(legacy),
+        ..History::default()
+    };
+    if let Err(e) = write_locked(&lock, &history) {
+        log!("Failed to write migrated launch history: {}", e);
+    }
+
+    history.entries
+}
+
+// Frequency grows logarithmically so heavy use doesn't drown out everything
+// else, while the recency part halves every `half_life_hours`.
+pub fn frecency(entry: &HeatmapEntry, now: u64, ranking: &Ranking) -> i64 {
+    let age_hours = now.saturating_sub(entry.last_used) as f64 / 3600.0;
+    let decay = match ranking.half_life_hours {
+        half_life if half_life > 0.0 => 0.5_f64.powf(age_hours / half_life),
+        _ => 0.0,
+    };
+
+    let frequency = ranking.frequency_weight * (entry.count as f64).ln_1p();
+    let recency = ranking.recency_weight * decay;
+
+    (frequency + recency).round() as i64
+}
+
+fn local_time(timestamp: u64) -> Option<DateTime<Local>> {
+    Local.timestamp_opt(timestamp as i64, 0).single()
+}
+
+// Rewards entries whose past launches cluster around the current hour and
+// fall on the current weekday.
+pub fn time_context(entry: &HeatmapEntry, now: u64, ranking: &Ranking) -> i64 {
+    let Some(now) = local_time(now) else {
+        return 0;
+    };
+
+    let (mut same_hour, mut same_weekday) = (0, 0);
+    for launched in entry.launches.iter().filter_map(|&time| local_time(time)) {
+        let hours_apart = launched.hour().abs_diff(now.hour());
+        if hours_apart.min(24 - hours_apart) <= 1 {
+            same_hour += 1;
+        }
+        if launched.weekday() == now.weekday() {
+            same_weekday += 1;
+        }
+    }
+
+    let samples = entry.launches.len() as f64 + TIME_CONTEXT_PRIOR;
+    let hour_share = same_hour as f64 / samples;
+    let weekday_share = same_weekday as f64 / samples;
+
+    (ranking.time_of_day_weight * hour_share + ranking.weekday_weight * weekday_share).round()
+        as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u64 = 3600;
+    const NOW: u64 = 1_700_000_000;
+
+    fn entry(count: u32, hours_ago: u64) -> HeatmapEntry {
+        HeatmapEntry {
+            count,
+            last_used: NOW - hours_ago * HOUR,
+            launches: Vec::new(),
+        }
+    }
+
+    fn score(count: u32, hours_ago: u64) -> i64 {
+        frecency(&entry(count, hours_ago), NOW, &Ranking::default())
+    }
+
+    #[test]
+    fn recent_use_ranks_higher_at_equal_count() {
+        assert!(score(5, 1) > score(5, 24));
+        assert!(score(5, 24) > score(5, 24 * 30));
+    }
+
+    #[test]
+    fn frequent_use_ranks_higher_at_equal_recency() {
+        assert!(score(50, 24) > score(5, 24));
+        assert!(score(5, 24) > score(1, 24));
+    }
+
+    #[test]
+    fn heavy_use_yesterday_beats_single_use_just_now() {
+        assert!(score(500, 24) > score(1, 0));
+    }
+
+    #[test]
+    fn score_never_goes_negative() {
+        assert!(score(1, 24 * 365 * 10) >= 0);
+    }
+
+    #[test]
+    fn future_timestamps_count_as_just_used() {
+        assert_eq!(
+            frecency(&entry(1, 0), NOW - HOUR, &Ranking::default()),
+            score(1, 0)
+        );
+    }
+
+    #[test]
+    fn recency_halves_after_half_life() {
+        let ranking = Ranking {
+            half_life_hours: 10.0,
+            frequency_weight: 0.0,
+            recency_weight: 1000.0,
+            ..Ranking::default()
+        };
+
+        assert_eq!(frecency(&entry(1, 0), NOW, &ranking), 1000);
+        assert_eq!(frecency(&entry(1, 10), NOW, &ranking), 500);
+        assert_eq!(frecency(&entry(1, 20), NOW, &ranking), 250);
+    }
+
+    #[test]
+    fn weights_shift_the_balance() {
+        let recency_heavy = Ranking {
+            frequency_weight: 10.0,
+            ..Ranking::default()
+        };
+
+        assert!(
+            frecency(&entry(1, 0), NOW, &recency_heavy)
+                > frecency(&entry(500, 24), NOW, &recency_heavy)
+        );
+    }
+
+    fn pair(query: &str, key: &str, count: u32) -> QueryEntry {
+        QueryEntry {
+            query: query.to_string(),
+            key: key.to_string(),
+            count,
+            last_used: NOW,
+        }
+    }
+
+    fn history_with(queries: Vec<QueryEntry>) -> History {
+        History {
+            queries,
+            ..History::default()
+        }
+    }
+
+    #[test]
+    fn usual_pick_for_a_query_gets_the_largest_share() {
+        let history = history_with(vec![
+            pair("te", "org.telegram.desktop.desktop", 9),
+            pair("te", "foot.desktop", 1),
+        ]);
+        let shares = history.query_shares("te");
+
+        assert_eq!(shares["org.telegram.desktop.desktop"], 0.9);
+        assert_eq!(shares["foot.desktop"], 0.1);
+    }
+
+    #[test]
+    fn extending_query_keeps_the_learned_pick() {
+        let history = history_with(vec![pair("te", "org.telegram.desktop.desktop", 3)]);
+
+        assert!(history
+            .query_shares("Tel")
+            .contains_key("org.telegram.desktop.desktop"));
+        assert!(history.query_shares("t").is_empty());
+        assert!(history.query_shares("fi").is_empty());
+    }
+
+    fn at(day: u32, hour: u32) -> u64 {
+        // 2024-05-06 is a Monday.
+        Local
+            .with_ymd_and_hms(2024, 5, 6 + day, hour, 0, 0)
+            .unwrap()
+            .timestamp() as u64
+    }
+
+    fn launched_at(launches: Vec<u64>) -> HeatmapEntry {
+        HeatmapEntry {
+            count: launches.len() as u32,
+            last_used: launches.iter().copied().max().unwrap_or_default(),
+            launches,
+        }
+    }
+
+    #[test]
+    fn apps_opened_at_this_hour_rank_higher() {
+        let ranking = Ranking::default();
+        let mail = launched_at((0..5).map(|day| at(day, 8)).collect());
+        let games = launched_at((0..5).map(|day| at(day, 20)).collect());
+
+        let morning = at(7, 8);
+        assert!(time_context(&mail, morning, &ranking) > time_context(&games, morning, &ranking));
+
+        let evening = at(7, 21);
+        assert!(time_context(&games, evening, &ranking) > time_context(&mail, evening, &ranking));
+    }
+
+    #[test]
+    fn apps_opened_on_this_weekday_rank_higher() {
+        let ranking = Ranking::default();
+        let weekly = launched_at(vec![at(0, 12), at(7, 12)]);
+        let daily = launched_at(vec![at(1, 12), at(2, 12)]);
+
+        let monday = at(14, 15);
+        assert!(time_context(&weekly, monday, &ranking) > time_context(&daily, monday, &ranking));
+    }
+
+    #[test]
+    fn a_single_launch_counts_less_than_a_habit() {
+        let ranking = Ranking::default();
+        let once = launched_at(vec![at(0, 8)]);
+        let habit = launched_at(
+            (0..20)
+                .map(|day| at(day, if day % 3 == 0 { 20 } else { 8 }))
+                .collect(),
+        );
+
+        let morning = at(21, 8);
+        assert!(time_context(&habit, morning, &ranking) > time_context(&once, morning, &ranking));
+    }
+
+    #[test]
+    fn entries_without_launch_log_get_no_boost() {
+        assert_eq!(time_context(&entry(10, 1), NOW, &Ranking::default()), 0);
+    }
+}
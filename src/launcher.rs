@@ -1,182 +1,872 @@
-use crate::log;
+use crate::{
+    cache::{self, DirRecord, FileRecord, IndexCache},
+    config, dbus,
+    exec::{self, Activation, ExecArg, ExecContext},
+    history::{self, HeatmapEntry},
+    log,
+};
+use freedesktop_entry_parser::AttrSelector;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use once_cell::sync::Lazy;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs,
-    path::PathBuf,
-    time::{SystemTime, UNIX_EPOCH},
+    path::{Path, PathBuf},
+    sync::mpsc::channel,
+    thread,
+    time::Duration,
 };
 use tokio::sync::RwLock;
 
+static LOCALE_CANDIDATES: Lazy<Vec<String>> = Lazy::new(get_locale_candidates);
+
 pub static APP_CACHE: Lazy<RwLock<HashMap<String, AppEntry>>> =
     Lazy::new(|| RwLock::new(HashMap::with_capacity(2000)));
 
-#[derive(Clone, Debug)]
+// Executables already covered by a desktop entry, kept in step with
+// APP_CACHE so searching $PATH doesn't rebuild it on every keystroke.
+pub static APP_EXECUTABLES: Lazy<RwLock<HashSet<String>>> =
+    Lazy::new(|| RwLock::new(HashSet::new()));
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DesktopAction {
+    pub id: String,
     pub name: String,
+    pub untranslated_name: String,
     pub exec: String,
+    pub exec_args: Vec<ExecArg>,
     pub icon_name: Option<String>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AppEntry {
     pub name: String,
+    pub desktop_id: String,
     pub description: String,
     pub path: String,
     pub exec: String,
+    pub activation: Activation,
     pub icon_name: String,
     pub launch_count: u32,
     pub last_used: Option<u64>,
     pub entry_type: EntryType,
     pub score_boost: i64,
     pub keywords: Vec<String>,
+    pub generic_name: String,
+    pub executable: String,
+    pub startup_wm_class: String,
+    pub untranslated_name: String,
+    pub untranslated_generic_name: String,
+    pub untranslated_keywords: Vec<String>,
     pub categories: Vec<String>,
     pub terminal: bool,
+    pub working_dir: Option<String>,
+    pub startup_notify: bool,
+    pub dbus_activatable: bool,
+    pub action_id: Option<String>,
     pub actions: Vec<DesktopAction>,
+    pub search_terms: Vec<SearchTerm>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SearchField {
+    Name,
+    Executable,
+    Keyword,
+    GenericName,
+    WmClass,
+    DesktopId,
+    Category,
+}
+
+// Lowercased once when the entry is parsed, so searching doesn't have to
+// allocate for every field of every application on each keystroke.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SearchTerm {
+    pub field: SearchField,
+    pub text: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DesktopFile {
+    pub entry: Option<AppEntry>,
+    pub try_exec: Option<String>,
+}
+
+impl DesktopFile {
+    pub fn resolve(self) -> Option<AppEntry> {
+        let entry = self.entry?;
+
+        if let Some(try_exec) = &self.try_exec {
+            if exec::find_executable(try_exec).is_none() {
+                log!("Skipping {}: TryExec={} not found", entry.path, try_exec);
+                return None;
+            }
+        }
+
+        Some(entry)
+    }
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
 pub enum EntryType {
     Application,
+    Action,
+    Binary,
+    Calculation,
+    WebSearch,
+    Command,
+    File,
 }
 
-static HEATMAP_PATH: &str = "~/.local/share/hyprlauncher/heatmap.toml";
+impl EntryType {
+    pub const ALL: [EntryType; 7] = [
+        EntryType::Application,
+        EntryType::Action,
+        EntryType::Binary,
+        EntryType::Calculation,
+        EntryType::WebSearch,
+        EntryType::Command,
+        EntryType::File,
+    ];
 
-static DESKTOP_PATHS: &[&str] = &[
-    "/usr/share/applications",
+    pub fn css_class(self) -> &'static str {
+        match self {
+            EntryType::Application => "entry-application",
+            EntryType::Action => "entry-action",
+            EntryType::Binary => "entry-binary",
+            EntryType::Calculation => "entry-calculation",
+            EntryType::WebSearch => "entry-web-search",
+            EntryType::Command => "entry-command",
+            EntryType::File => "entry-file",
+        }
+    }
+
+    pub fn default_icon(self) -> &'static str {
+        match self {
+            EntryType::Application | EntryType::Action | EntryType::Binary => {
+                "application-x-executable"
+            }
+            EntryType::Calculation => "accessories-calculator",
+            EntryType::WebSearch => "web-browser",
+            EntryType::Command => "utilities-terminal",
+            EntryType::File => "text-x-generic",
+        }
+    }
+
+    // One-off results like calculations, searches and typed commands would
+    // only clutter the launch history.
+    pub fn records_history(self) -> bool {
+        matches!(
+            self,
+            EntryType::Application | EntryType::Action | EntryType::Binary
+        )
+    }
+}
+
+const APP_WATCH_DEBOUNCE: Duration = Duration::from_millis(250);
+
+static USER_DESKTOP_PATHS: &[&str] = &["applications", "flatpak/exports/share/applications"];
+
+static SYSTEM_DESKTOP_PATHS: &[&str] = &[
     "/usr/local/share/applications",
+    "/usr/share/applications",
     "/var/lib/flatpak/exports/share/applications",
-    "~/.local/share/applications",
-    "~/.local/share/flatpak/exports/share/applications",
 ];
 
-#[derive(Serialize, Deserialize)]
-pub struct HeatmapEntry {
-    pub count: u32,
-    pub last_used: u64,
+impl AppEntry {
+    pub fn new(name: String, entry_type: EntryType, activation: Activation) -> Self {
+        Self {
+            name,
+            desktop_id: String::new(),
+            description: String::new(),
+            path: String::new(),
+            exec: activation.to_command_line(),
+            activation,
+            icon_name: String::from(entry_type.default_icon()),
+            launch_count: 0,
+            last_used: None,
+            entry_type,
+            score_boost: 0,
+            keywords: Vec::new(),
+            generic_name: String::new(),
+            executable: String::new(),
+            startup_wm_class: String::new(),
+            untranslated_name: String::new(),
+            untranslated_generic_name: String::new(),
+            untranslated_keywords: Vec::new(),
+            categories: Vec::new(),
+            terminal: false,
+            working_dir: None,
+            startup_notify: true,
+            dbus_activatable: false,
+            action_id: None,
+            actions: Vec::new(),
+            search_terms: Vec::new(),
+        }
+    }
+
+    fn build_search_terms(&self) -> Vec<SearchTerm> {
+        let fields: [(SearchField, Vec<&str>); 7] = [
+            (SearchField::Name, vec![&self.name, &self.untranslated_name]),
+            (SearchField::Executable, vec![&self.executable]),
+            (
+                SearchField::Keyword,
+                self.keywords
+                    .iter()
+                    .chain(&self.untranslated_keywords)
+                    .map(String::as_str)
+                    .collect(),
+            ),
+            (
+                SearchField::GenericName,
+                vec![&self.generic_name, &self.untranslated_generic_name],
+            ),
+            (SearchField::WmClass, vec![&self.startup_wm_class]),
+            (
+                SearchField::DesktopId,
+                vec![self.desktop_id.trim_end_matches(".desktop")],
+            ),
+            (
+                SearchField::Category,
+                self.categories.iter().map(String::as_str).collect(),
+            ),
+        ];
+
+        let mut terms: Vec<SearchTerm> = Vec::new();
+        for (field, values) in fields {
+            for text in values.iter().filter(|value| !value.is_empty()) {
+                let text = text.to_lowercase();
+                if !terms
+                    .iter()
+                    .any(|term| term.field == field && term.text == text)
+                {
+                    terms.push(SearchTerm { field, text });
+                }
+            }
+        }
+
+        terms
+    }
+
+    pub fn history_key(&self) -> Option<String> {
+        if !self.entry_type.records_history() {
+            return None;
+        }
+
+        match self.entry_type {
+            EntryType::Action => Some(format!(
+                "{}:{}",
+                self.desktop_id,
+                self.action_id.as_deref().unwrap_or_default()
+            )),
+            EntryType::Binary => Some(format!("bin:{}", self.path)),
+            _ if !self.desktop_id.is_empty() => Some(self.desktop_id.clone()),
+            _ => None,
+        }
+    }
 }
 
-pub fn increment_launch_count(app: &AppEntry) -> Result<u32, std::io::Error> {
-    let app_name = app.name.clone();
-    let count = app.launch_count + 1;
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-
-    std::thread::spawn(move || {
-        let mut cache = APP_CACHE.blocking_write();
-        if let Some(cached_app) = cache.get_mut(&app_name) {
-            cached_app.launch_count = count;
-            cached_app.last_used = Some(now);
+pub fn increment_launch_count(app: &AppEntry, query: &str, max_queries: usize) {
+    let Some(key) = app.history_key() else {
+        return;
+    };
+    let desktop_id = (app.entry_type == EntryType::Application).then(|| app.desktop_id.clone());
+    let query = query.to_string();
+
+    std::thread::spawn(move || match history::record(&key, &query, max_queries) {
+        Ok((count, last_used)) => {
+            let Some(desktop_id) = desktop_id else {
+                return;
+            };
+            if let Some(cached_app) = APP_CACHE.blocking_write().get_mut(&desktop_id) {
+                cached_app.launch_count = count;
+                cached_app.last_used = Some(last_used);
+            }
         }
-        save_heatmap(&app_name, count).unwrap();
+        Err(e) => log!("Failed to update heatmap for {}: {}", key, e),
     });
-
-    Ok(count)
 }
 
-pub fn update_heatmap(name: &str, count: u32) -> Result<(), std::io::Error> {
-    let path = shellexpand::tilde(HEATMAP_PATH).to_string();
-    let mut heatmap: HashMap<String, HeatmapEntry> = load_heatmap()?;
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-
-    heatmap.insert(
-        name.to_string(),
-        HeatmapEntry {
-            count,
-            last_used: now,
-        },
-    );
+// Older heatmaps were keyed by the display name, which breaks on locale
+// changes and mixes up apps sharing a name, so re-key them once.
+fn legacy_heatmap_key(name: &str, apps: &[&AppEntry]) -> Option<String> {
+    let app = apps
+        .iter()
+        .find(|app| app.name == name)
+        .or_else(|| apps.iter().find(|app| app.untranslated_name == name));
+    if let Some(app) = app {
+        return Some(app.desktop_id.clone());
+    }
 
-    if let Ok(contents) = toml::to_string(&heatmap) {
-        let _ = fs::write(path, contents);
+    // Action keys were written with the untranslated action name, so both
+    // spellings of either part are tried.
+    let action = apps.iter().find_map(|app| {
+        app.actions
+            .iter()
+            .find(|action| {
+                [&app.name, &app.untranslated_name].iter().any(|app_name| {
+                    [&action.name, &action.untranslated_name]
+                        .iter()
+                        .any(|action_name| format!("{} - {}", app_name, action_name) == name)
+                })
+            })
+            .map(|action| format!("{}:{}", app.desktop_id, action.id))
+    });
+    if action.is_some() {
+        return action;
     }
 
-    Ok(())
+    Some(name)
+        .filter(|name| !name.contains(char::is_whitespace))
+        .and_then(exec::find_executable)
+        .map(|path| format!("bin:{}", path.to_string_lossy()))
 }
 
-pub fn load_heatmap() -> Result<HashMap<String, HeatmapEntry>, std::io::Error> {
-    let path = shellexpand::tilde(HEATMAP_PATH).to_string();
-    Ok(fs::read_to_string(path)
-        .ok()
-        .and_then(|contents| toml::from_str(&contents).ok())
-        .unwrap_or_else(|| HashMap::with_capacity(100)))
+fn migrate_heatmap(apps: &HashMap<String, AppEntry>) -> HashMap<String, HeatmapEntry> {
+    history::migrate(|legacy| {
+        let mut sorted_apps: Vec<&AppEntry> = apps.values().collect();
+        sorted_apps.sort_by(|a, b| a.desktop_id.cmp(&b.desktop_id));
+
+        let mut entries: HashMap<String, HeatmapEntry> = HashMap::with_capacity(legacy.len());
+        for (name, legacy_entry) in legacy {
+            let Some(key) = legacy_heatmap_key(&name, &sorted_apps) else {
+                log!("Dropping heatmap entry {:?}: no matching application", name);
+                continue;
+            };
+
+            let entry = entries.entry(key).or_insert(HeatmapEntry {
+                count: 0,
+                last_used: 0,
+                launches: Vec::new(),
+            });
+            entry.count += legacy_entry.count;
+            entry.last_used = entry.last_used.max(legacy_entry.last_used);
+        }
+
+        log!(
+            "Migrated heatmap to {} desktop ID keyed entries",
+            entries.len()
+        );
+        entries
+    })
 }
 
 pub fn get_desktop_paths() -> Vec<PathBuf> {
-    let mut paths = Vec::with_capacity(10);
+    let mut paths: Vec<PathBuf> = Vec::with_capacity(10);
+    let mut push_unique = |path: PathBuf| {
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+    };
+
+    let data_home = config::xdg_dir("XDG_DATA_HOME", "~/.local/share");
+    for path in USER_DESKTOP_PATHS {
+        push_unique(data_home.join(path));
+    }
 
     if let Ok(xdg_dirs) = std::env::var("XDG_DATA_DIRS") {
-        paths.extend(
-            xdg_dirs
-                .split(':')
-                .map(|dir| PathBuf::from(format!("{}/applications", dir))),
-        );
+        for dir in xdg_dirs.split(':').filter(|dir| !dir.is_empty()) {
+            push_unique(PathBuf::from(dir).join("applications"));
+        }
     }
 
-    paths.extend(
-        DESKTOP_PATHS
-            .iter()
-            .map(|&path| PathBuf::from(shellexpand::tilde(path).to_string())),
-    );
+    for path in SYSTEM_DESKTOP_PATHS {
+        push_unique(PathBuf::from(path));
+    }
 
     paths
 }
 
-pub async fn load_applications() -> Result<(), std::io::Error> {
-    log!("Starting application loading process");
-    let heatmap = load_heatmap()?;
+struct DesktopScan {
+    files: Vec<(String, PathBuf)>,
+    dirs: Vec<(PathBuf, DirRecord)>,
+}
+
+fn collect_desktop_files(dir: &Path, index: &IndexCache) -> DesktopScan {
+    let mut scan = DesktopScan {
+        files: Vec::new(),
+        dirs: Vec::new(),
+    };
+    let mut visited = HashSet::new();
+    walk_desktop_dir(dir, "", &mut visited, index, &mut scan);
+    scan
+}
+
+fn read_dir_children(dir: &Path) -> Vec<(String, bool)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut children: Vec<(String, bool)> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let path = entry.path();
+            let file_name = path.file_name()?.to_str()?.to_string();
+            Some((file_name, path.is_dir()))
+        })
+        .collect();
+    children.sort();
+    children
+}
+
+fn walk_desktop_dir(
+    dir: &Path,
+    prefix: &str,
+    visited: &mut HashSet<PathBuf>,
+    index: &IndexCache,
+    scan: &mut DesktopScan,
+) {
+    let Ok(canonical) = fs::canonicalize(dir) else {
+        return;
+    };
+    if !visited.insert(canonical) {
+        log!(
+            "Skipping {}: already visited (symlink loop?)",
+            dir.display()
+        );
+        return;
+    }
+
+    let mtime = cache::modified(dir);
+    let children = match index.dirs.get(dir) {
+        Some(record) if record.mtime == mtime => record.children.clone(),
+        _ => read_dir_children(dir),
+    };
+
+    for (file_name, is_dir) in &children {
+        let path = dir.join(file_name);
+
+        if *is_dir {
+            let sub_prefix = format!("{}{}-", prefix, file_name);
+            walk_desktop_dir(&path, &sub_prefix, visited, index, scan);
+        } else if path.extension().and_then(|e| e.to_str()) == Some("desktop") {
+            scan.files.push((format!("{}{}", prefix, file_name), path));
+        }
+    }
+
+    scan.dirs
+        .push((dir.to_path_buf(), DirRecord { mtime, children }));
+}
+
+fn resolve_desktop_files(desktop_paths: &[PathBuf], index: &IndexCache) -> DesktopScan {
+    let mut seen_ids = HashSet::with_capacity(2000);
+    let mut resolved = DesktopScan {
+        files: Vec::with_capacity(2000),
+        dirs: Vec::new(),
+    };
+
+    let scans: Vec<DesktopScan> = desktop_paths
+        .par_iter()
+        .map(|dir| collect_desktop_files(dir, index))
+        .collect();
+
+    for scan in scans {
+        resolved.dirs.extend(scan.dirs);
+        resolved
+            .files
+            .extend(scan.files.into_iter().filter(|(desktop_id, path)| {
+                let is_new = seen_ids.insert(desktop_id.clone());
+                if !is_new {
+                    log!(
+                        "Skipping {}: masked by a higher precedence {}",
+                        path.display(),
+                        desktop_id
+                    );
+                }
+                is_new
+            }));
+    }
+
+    resolved
+}
+
+fn apply_heatmap(entry: &mut AppEntry, heatmap: &HashMap<String, HeatmapEntry>) {
+    if let Some(heat_entry) = heatmap.get(&entry.desktop_id) {
+        entry.launch_count = heat_entry.count;
+        entry.last_used = Some(heat_entry.last_used);
+    }
+}
+
+fn scan_applications() -> Result<HashMap<String, AppEntry>, std::io::Error> {
     let desktop_paths = get_desktop_paths();
     log!("Scanning desktop entry paths: {:?}", desktop_paths);
     let mut apps = HashMap::with_capacity(2000);
 
-    let entries: Vec<_> = desktop_paths
+    let index = IndexCache::load();
+    let scan = resolve_desktop_files(&desktop_paths, &index);
+
+    let records: Vec<(PathBuf, FileRecord, bool)> = scan
+        .files
         .par_iter()
-        .flat_map_iter(|path| {
-            if let Ok(entries) = std::fs::read_dir(path) {
-                entries
-                    .filter_map(Result::ok)
-                    .filter(|e| {
-                        matches!(
-                            e.path().extension().and_then(|e| e.to_str()),
-                            Some("desktop")
-                        )
-                    })
-                    .filter_map(|entry| parse_desktop_entry(&entry.path()))
-                    .collect::<Vec<_>>()
-            } else {
-                Vec::new()
+        .map(|(desktop_id, path)| {
+            let mtime = cache::modified(path);
+            match index.files.get(path) {
+                Some(record) if record.mtime == mtime && record.desktop_id == *desktop_id => {
+                    (path.clone(), record.clone(), false)
+                }
+                _ => {
+                    let record = FileRecord {
+                        mtime,
+                        desktop_id: desktop_id.clone(),
+                        desktop_file: read_desktop_file(path, desktop_id),
+                    };
+                    (path.clone(), record, true)
+                }
             }
         })
         .collect();
 
-    for mut entry in entries {
-        if let Some(heat_entry) = heatmap.get(&entry.name) {
-            entry.launch_count = heat_entry.count;
-            entry.last_used = Some(heat_entry.last_used);
+    let parsed = records.iter().filter(|(_, _, fresh)| *fresh).count();
+    log!(
+        "Parsed {} desktop entries, reused {} from the index cache",
+        parsed,
+        records.len() - parsed
+    );
+
+    for (_, record, _) in &records {
+        if let Some(entry) = record.desktop_file.clone().resolve() {
+            apps.insert(entry.desktop_id.clone(), entry);
         }
-        apps.insert(entry.name.clone(), entry);
     }
 
+    let heatmap = migrate_heatmap(&apps);
+    for entry in apps.values_mut() {
+        apply_heatmap(entry, &heatmap);
+    }
+
+    let dirs: HashMap<PathBuf, DirRecord> = scan.dirs.into_iter().collect();
+    if parsed > 0 || records.len() != index.files.len() || dirs != index.dirs {
+        let files = records
+            .into_iter()
+            .map(|(path, record, _)| (path, record))
+            .collect();
+        IndexCache::new(dirs, files).save();
+    }
+
+    Ok(apps)
+}
+
+pub async fn load_applications() -> Result<(), std::io::Error> {
+    log!("Starting application loading process");
+    let apps = scan_applications()?;
+
     log!("Loaded {} total applications", apps.len());
+    *APP_EXECUTABLES.write().await = collect_executables(&apps);
     let mut cache = APP_CACHE.write().await;
     *cache = apps;
 
     Ok(())
 }
 
+pub fn watch_applications() {
+    let desktop_paths = get_desktop_paths();
+
+    thread::spawn(move || {
+        let (tx, rx) = channel();
+
+        let mut watcher = match RecommendedWatcher::new(tx, notify::Config::default()) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                log!("Failed to create application watcher: {}", e);
+                return;
+            }
+        };
+
+        let mut watched = HashMap::new();
+        sync_watches(
+            &mut watcher,
+            &desktop_paths,
+            RecursiveMode::Recursive,
+            &mut watched,
+        );
+
+        loop {
+            let mut changed = HashSet::new();
+
+            match rx.recv() {
+                Ok(Ok(event)) => collect_changed_paths(event, &mut changed),
+                Ok(Err(e)) => {
+                    log!("Application watch error: {:?}", e);
+                    continue;
+                }
+                Err(_) => break,
+            }
+
+            while let Ok(result) = rx.recv_timeout(APP_WATCH_DEBOUNCE) {
+                if let Ok(event) = result {
+                    collect_changed_paths(event, &mut changed);
+                }
+            }
+
+            changed.extend(sync_watches(
+                &mut watcher,
+                &desktop_paths,
+                RecursiveMode::Recursive,
+                &mut watched,
+            ));
+            if !changed.is_empty() {
+                apply_desktop_changes(&desktop_paths, &changed);
+            }
+        }
+    });
+}
+
+// Directories that don't exist yet, like ~/.local/share/applications before
+// the first user entry or ~/.cargo/bin before rustup is installed, are
+// covered by watching their nearest existing ancestor until they appear.
+// Returns the directories that started being watched by this call.
+pub fn sync_watches(
+    watcher: &mut RecommendedWatcher,
+    dirs: &[PathBuf],
+    mode: RecursiveMode,
+    watched: &mut HashMap<PathBuf, RecursiveMode>,
+) -> Vec<PathBuf> {
+    let mut wanted: HashMap<PathBuf, RecursiveMode> = HashMap::new();
+    for path in dirs {
+        if path.is_dir() {
+            wanted.insert(path.clone(), mode);
+        } else if let Some(ancestor) = path.ancestors().skip(1).find(|dir| dir.is_dir()) {
+            wanted
+                .entry(ancestor.to_path_buf())
+                .or_insert(RecursiveMode::NonRecursive);
+        }
+    }
+
+    let appeared: Vec<PathBuf> = dirs
+        .iter()
+        .filter(|path| wanted.get(*path) == Some(&mode) && watched.get(*path) != Some(&mode))
+        .cloned()
+        .collect();
+
+    for (path, mode) in watched.iter() {
+        if wanted.get(path) != Some(mode) {
+            let _ = watcher.unwatch(path);
+        }
+    }
+    for (path, mode) in &wanted {
+        if watched.get(path) == Some(mode) {
+            continue;
+        }
+        if let Err(e) = watcher.watch(path, *mode) {
+            log!("Failed to watch {:?}: {}", path, e);
+        }
+    }
+    *watched = wanted;
+
+    appeared
+}
+
+pub fn collect_changed_paths(event: notify::Event, changed: &mut HashSet<PathBuf>) {
+    if !matches!(event.kind, notify::EventKind::Access(_)) {
+        changed.extend(event.paths);
+    }
+}
+
+fn apply_desktop_changes(desktop_paths: &[PathBuf], changed: &HashSet<PathBuf>) {
+    let mut affected_ids = HashSet::new();
+    let mut full_rescan = false;
+
+    for path in changed {
+        let Some(relative) = desktop_paths
+            .iter()
+            .find_map(|root| path.strip_prefix(root).ok())
+        else {
+            continue;
+        };
+
+        if path.extension().and_then(|e| e.to_str()) == Some("desktop") {
+            let desktop_id = relative
+                .iter()
+                .map(|component| component.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("-");
+            affected_ids.insert(desktop_id);
+        } else if path.is_dir()
+            || APP_CACHE
+                .blocking_read()
+                .values()
+                .any(|app| Path::new(&app.path).starts_with(path))
+        {
+            full_rescan = true;
+        }
+    }
+
+    if full_rescan {
+        log!("Desktop entry directories changed, rescanning all applications");
+        match scan_applications() {
+            Ok(apps) => {
+                *APP_EXECUTABLES.blocking_write() = collect_executables(&apps);
+                *APP_CACHE.blocking_write() = apps;
+            }
+            Err(e) => log!("Failed to rescan applications: {}", e),
+        }
+        return;
+    }
+
+    if affected_ids.is_empty() {
+        return;
+    }
+
+    let heatmap = history::load().entries;
+    let candidates: HashMap<String, PathBuf> =
+        resolve_desktop_files(desktop_paths, &IndexCache::default())
+            .files
+            .into_iter()
+            .filter(|(desktop_id, _)| affected_ids.contains(desktop_id))
+            .collect();
+
+    let updates: Vec<(String, Option<AppEntry>)> = affected_ids
+        .into_iter()
+        .map(|desktop_id| {
+            let entry = candidates.get(&desktop_id).and_then(|path| {
+                let mut entry = read_desktop_file(path, &desktop_id).resolve()?;
+                apply_heatmap(&mut entry, &heatmap);
+                Some(entry)
+            });
+            (desktop_id, entry)
+        })
+        .collect();
+
+    let mut cache = APP_CACHE.blocking_write();
+    for (desktop_id, entry) in updates {
+        match entry {
+            Some(entry) => {
+                log!("Updated application {}", desktop_id);
+                cache.insert(desktop_id, entry);
+            }
+            None => {
+                if cache.remove(&desktop_id).is_some() {
+                    log!("Removed application {}", desktop_id);
+                }
+            }
+        }
+    }
+    *APP_EXECUTABLES.blocking_write() = collect_executables(&cache);
+}
+
+fn collect_executables(apps: &HashMap<String, AppEntry>) -> HashSet<String> {
+    apps.values()
+        .map(|app| app.executable.clone())
+        .filter(|executable| !executable.is_empty())
+        .collect()
+}
+
+fn get_locale_candidates() -> Vec<String> {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default();
+
+    let mut locales: Vec<String> = Vec::new();
+    if expand_locale(&locale).is_empty() {
+        return Vec::new();
+    }
+    if let Ok(language) = std::env::var("LANGUAGE") {
+        locales.extend(
+            language
+                .split(':')
+                .filter(|l| !l.is_empty())
+                .map(String::from),
+        );
+    }
+    locales.push(locale);
+
+    let mut candidates = Vec::new();
+    for candidate in locales.iter().flat_map(|locale| expand_locale(locale)) {
+        if !candidates.contains(&candidate) {
+            candidates.push(candidate);
+        }
+    }
+    candidates
+}
+
+fn expand_locale(locale: &str) -> Vec<String> {
+    let (locale, modifier) = match locale.split_once('@') {
+        Some((locale, modifier)) => (locale, Some(modifier)),
+        None => (locale, None),
+    };
+    let locale = locale.split('.').next().unwrap_or_default();
+    let (lang, country) = match locale.split_once('_') {
+        Some((lang, country)) => (lang, Some(country)),
+        None => (locale, None),
+    };
+
+    if lang.is_empty() || lang == "C" || lang == "POSIX" {
+        return Vec::new();
+    }
+
+    let mut candidates = Vec::with_capacity(4);
+    if let (Some(country), Some(modifier)) = (country, modifier) {
+        candidates.push(format!("{}_{}@{}", lang, country, modifier));
+    }
+    if let Some(country) = country {
+        candidates.push(format!("{}_{}", lang, country));
+    }
+    if let Some(modifier) = modifier {
+        candidates.push(format!("{}@{}", lang, modifier));
+    }
+    candidates.push(lang.to_string());
+    candidates
+}
+
+fn get_localized<T: AsRef<str>>(section: &AttrSelector<'_, T>, key: &str) -> Option<String> {
+    LOCALE_CANDIDATES
+        .iter()
+        .find_map(|locale| section.attr_with_param(key, locale))
+        .or_else(|| section.attr(key))
+        .map(String::from)
+}
+
+fn read_desktop_file(path: &Path, desktop_id: &str) -> DesktopFile {
+    let Ok(entry) = freedesktop_entry_parser::parse_entry(path) else {
+        log!("Skipping {}: failed to parse desktop entry", path.display());
+        return DesktopFile {
+            entry: None,
+            try_exec: None,
+        };
+    };
+
+    let section = entry.section("Desktop Entry");
+    let try_exec = match section.attr("Type") {
+        Some("Link") => None,
+        _ => section.attr("TryExec").map(String::from),
+    };
+
+    DesktopFile {
+        entry: parse_desktop_entry(&entry, path, desktop_id),
+        try_exec,
+    }
+}
+
 #[inline]
-fn parse_desktop_entry(path: &std::path::Path) -> Option<AppEntry> {
-    let entry = freedesktop_entry_parser::parse_entry(path).ok()?;
+fn parse_desktop_entry(
+    entry: &freedesktop_entry_parser::Entry,
+    path: &Path,
+    desktop_id: &str,
+) -> Option<AppEntry> {
     let section = entry.section("Desktop Entry");
+    let location = path.to_string_lossy().into_owned();
+
+    let is_link = match section.attr("Type").unwrap_or("Application") {
+        "Application" => false,
+        "Link" => true,
+        other => {
+            log!("Skipping {}: unsupported Type={}", location, other);
+            return None;
+        }
+    };
+
+    if section.attr("Hidden") == Some("true") {
+        log!("Skipping {}: Hidden=true", location);
+        return None;
+    }
 
-    if section.attr("NoDisplay").map_or(false, |v| v == "true") {
+    if section.attr("NoDisplay") == Some("true") {
+        log!("Skipping {}: NoDisplay=true", location);
         return None;
     }
 
@@ -195,6 +885,7 @@ fn parse_desktop_entry(path: &std::path::Path) -> Option<AppEntry> {
             .map(|s| s.to_uppercase())
             .collect();
         if !desktops.iter().any(|d| allowed_desktops.contains(d)) {
+            log!("Skipping {}: not in OnlyShowIn={}", location, only_show_in);
             return None;
         }
     }
@@ -206,79 +897,113 @@ fn parse_desktop_entry(path: &std::path::Path) -> Option<AppEntry> {
             .map(|s| s.to_uppercase())
             .collect();
         if desktops.iter().any(|d| excluded_desktops.contains(d)) {
+            log!("Skipping {}: listed in NotShowIn={}", location, not_show_in);
             return None;
         }
     }
 
-    let lang = std::env::var("LC_ALL")
-        .or_else(|_| std::env::var("LC_MESSAGES"))
-        .or_else(|_| std::env::var("LANG"))
-        .unwrap_or_default();
-    let lang = lang.split('.').next().unwrap_or_default();
+    let name = match get_localized(&section, "Name") {
+        Some(name) => name,
+        None => {
+            log!("Skipping {}: missing Name", location);
+            return None;
+        }
+    };
+    let icon_key = section.attr("Icon");
 
-    let get_localized = |base_key: &str| -> Option<String> {
-        if section.has_attr_with_param(base_key, lang) {
-            section.attr_with_param(base_key, lang).map(String::from)
-        } else {
-            section.attr(base_key).map(String::from)
+    let exec_ctx = ExecContext {
+        icon: icon_key,
+        name: &name,
+        location: &location,
+    };
+    let dbus_activatable = !is_link
+        && section.attr("DBusActivatable") == Some("true")
+        && dbus::bus_name(desktop_id).is_some();
+    let activation = if is_link {
+        match section.attr("URL") {
+            Some(url) => Activation::OpenUri(url.to_string()),
+            None => {
+                log!("Skipping {}: Type=Link without URL", location);
+                return None;
+            }
+        }
+    } else {
+        match exec::parse_exec(section.attr("Exec").unwrap_or_default(), &exec_ctx) {
+            // Exec may only be left out when the application is started over D-Bus.
+            Some(args) if args.is_empty() && !dbus_activatable => {
+                log!("Skipping {}: missing Exec", location);
+                return None;
+            }
+            Some(args) => Activation::Spawn(args),
+            None => {
+                log!("Skipping {}: malformed Exec value", location);
+                return None;
+            }
         }
     };
+    let executable = match &activation {
+        Activation::Spawn(args) => exec::executable_name(args).unwrap_or_default(),
+        _ => String::new(),
+    };
 
-    let name = get_localized("Name")?;
-    let raw_exec = get_localized("Exec").unwrap_or_default();
+    let icon = String::from(icon_key.unwrap_or("application-x-executable"));
 
-    let exec = raw_exec
-        .split_whitespace()
-        .filter(|&arg| !arg.starts_with('%'))
-        .collect::<Vec<_>>()
-        .join(" ");
+    let generic_name = get_localized(&section, "GenericName").unwrap_or_default();
+    let desc = get_localized(&section, "Comment").unwrap_or_else(|| generic_name.clone());
+    let startup_wm_class = section
+        .attr("StartupWMClass")
+        .unwrap_or_default()
+        .to_string();
 
-    let icon = String::from(section.attr("Icon").unwrap_or("application-x-executable"));
+    let split_list = |value: &str| -> Vec<String> {
+        value
+            .split(';')
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect()
+    };
 
-    let desc = get_localized("Comment")
-        .or_else(|| get_localized("GenericName"))
+    let keywords = get_localized(&section, "Keywords")
+        .map(|k| split_list(&k))
         .unwrap_or_default();
 
-    let keywords = section
-        .attr("Keywords")
-        .map(|k| {
-            k.split(';')
-                .filter(|s| !s.is_empty())
-                .map(String::from)
-                .collect()
-        })
-        .unwrap_or_default();
+    let untranslated_name = section.attr("Name").unwrap_or(&name).to_string();
+    let untranslated_generic_name = section.attr("GenericName").unwrap_or_default().to_string();
+    let untranslated_keywords = section.attr("Keywords").map(split_list).unwrap_or_default();
 
     let categories = section
         .attr("Categories")
-        .map(|c| {
-            c.split(';')
-                .filter(|s| !s.is_empty())
-                .map(String::from)
-                .collect()
-        })
+        .map(split_list)
         .unwrap_or_default();
 
-    let terminal = section.attr("Terminal").map_or(false, |v| v == "true");
+    let terminal = section.attr("Terminal") == Some("true");
+    let working_dir = section
+        .attr("Path")
+        .filter(|dir| !dir.is_empty())
+        .map(String::from);
+    let startup_notify = section.attr("StartupNotify") != Some("false");
 
     let mut actions = Vec::new();
     if let Some(action_list) = section.attr("Actions") {
         for action_name in action_list.split(';').filter(|s| !s.is_empty()) {
             let section_name = format!("Desktop Action {}", action_name);
             let action_section = entry.section(&section_name);
-            if let Some(raw_action_exec) = action_section.attr("Exec") {
-                let action_exec = raw_action_exec
-                    .split_whitespace()
-                    .filter(|&arg| !arg.starts_with('%'))
-                    .collect::<Vec<_>>()
-                    .join(" ");
-
+            let action_exec_args = match action_section.attr("Exec") {
+                Some(raw_action_exec) => exec::parse_exec(raw_action_exec, &exec_ctx),
+                None if dbus_activatable => Some(Vec::new()),
+                None => None,
+            };
+            if let Some(action_exec_args) = action_exec_args {
                 let action = DesktopAction {
-                    name: action_section
+                    id: action_name.to_string(),
+                    name: get_localized(&action_section, "Name")
+                        .unwrap_or_else(|| action_name.to_string()),
+                    untranslated_name: action_section
                         .attr("Name")
                         .unwrap_or(action_name)
                         .to_string(),
-                    exec: action_exec,
+                    exec: exec::exec_to_string(&action_exec_args),
+                    exec_args: action_exec_args,
                     icon_name: action_section.attr("Icon").map(String::from),
                 };
                 actions.push(action);
@@ -286,23 +1011,27 @@ fn parse_desktop_entry(path: &std::path::Path) -> Option<AppEntry> {
         }
     }
 
-    Some(AppEntry {
-        name,
-        exec,
+    let mut entry = AppEntry {
+        desktop_id: desktop_id.to_string(),
         icon_name: icon,
         description: desc,
-        path: path.to_string_lossy().into_owned(),
-        launch_count: 0,
-        last_used: None,
-        entry_type: EntryType::Application,
-        score_boost: 0,
+        path: location,
         keywords,
+        generic_name,
+        executable,
+        startup_wm_class,
+        untranslated_name,
+        untranslated_generic_name,
+        untranslated_keywords,
         categories,
         terminal,
+        working_dir,
+        startup_notify,
+        dbus_activatable,
         actions,
-    })
-}
+        ..AppEntry::new(name, EntryType::Application, activation)
+    };
+    entry.search_terms = entry.build_search_terms();
 
-pub fn save_heatmap(name: &str, count: u32) -> Result<(), std::io::Error> {
-    update_heatmap(name, count)
+    Some(entry)
 }
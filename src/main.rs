@@ -1,7 +1,13 @@
 mod app;
+mod binaries;
+mod cache;
 mod config;
+mod dbus;
+mod exec;
+mod history;
 mod launcher;
 mod search;
+mod terminal;
 mod ui;
 
 use crate::app::App;
@@ -27,6 +33,14 @@ fn main() {
         std::process::exit(app.run());
     }
 
+    if args.len() > 1 && args[1] == "--clear-query-history" {
+        if let Err(e) = history::clear_queries() {
+            eprintln!("Failed to clear query history: {}", e);
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
+
     if args.len() > 1 {
         eprintln!("Unknown option: {}", args[1]);
         std::process::exit(1);
@@ -1,13 +1,16 @@
 use crate::{
-    config::{Config, WebSearch},
-    launcher::{self, AppEntry, EntryType, APP_CACHE},
+    binaries::{self, PathBinary, BINARY_INDEX},
+    config::{Config, Ranking, WebSearch},
+    exec::{Activation, ExecArg},
+    history::{self, HeatmapEntry},
+    launcher::{AppEntry, EntryType, SearchField, APP_CACHE, APP_EXECUTABLES},
 };
 use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
-use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use rink_core::{one_line, simple_context};
 use std::{
-    collections::HashMap,
-    os::unix::fs::PermissionsExt,
+    collections::{HashMap, HashSet},
+    path::PathBuf,
     sync::atomic::{AtomicU64, Ordering},
     time::{SystemTime, UNIX_EPOCH},
 };
@@ -19,11 +22,28 @@ use x11rb::{
 
 const BONUS_SCORE_ICON_NAME: i64 = 1000;
 const BONUS_SCORE_BINARY: i64 = 3000;
+const BONUS_SCORE_PATH_BINARY: i64 = 1500;
+const MAX_PATH_BINARY_RESULTS: usize = 5;
+const BONUS_SCORE_NAME_MATCH: i64 = 3000;
+const BONUS_SCORE_EXECUTABLE_MATCH: i64 = 2800;
 const BONUS_SCORE_KEYWORD_MATCH: i64 = 2500;
+const BONUS_SCORE_GENERIC_NAME_MATCH: i64 = 2400;
+const BONUS_SCORE_WM_CLASS_MATCH: i64 = 2200;
+const BONUS_SCORE_DESKTOP_ID_MATCH: i64 = 2100;
 const BONUS_SCORE_CATEGORY_MATCH: i64 = 2000;
 const BONUS_SCORE_WEB_SEARCH: i64 = -1000;
 const BONUS_SCORE_CALC: i64 = 3000;
+const BONUS_SCORE_FILE: i64 = 3000;
 const OPEN_WINDOW_PENALTY: i64 = -500;
+const FILE_URI_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+const SHELL_METACHARACTERS: &[char] = &[
+    '|', '&', ';', '<', '>', '(', ')', '$', '`', '*', '?', '[', '~', '\n',
+];
 
 static SEARCH_GENERATION: AtomicU64 = AtomicU64::new(0);
 
@@ -31,9 +51,45 @@ pub struct SearchResult {
     pub app: AppEntry,
     pub score: i64,
 }
-pub struct HistoryEntry {
-    last_used: u64,
-    use_count: i64,
+
+fn field_weight(field: SearchField) -> i64 {
+    match field {
+        SearchField::Name => BONUS_SCORE_NAME_MATCH,
+        SearchField::Executable => BONUS_SCORE_EXECUTABLE_MATCH,
+        SearchField::Keyword => BONUS_SCORE_KEYWORD_MATCH,
+        SearchField::GenericName => BONUS_SCORE_GENERIC_NAME_MATCH,
+        SearchField::WmClass => BONUS_SCORE_WM_CLASS_MATCH,
+        SearchField::DesktopId => BONUS_SCORE_DESKTOP_ID_MATCH,
+        SearchField::Category => BONUS_SCORE_CATEGORY_MATCH,
+    }
+}
+
+// Identifiers only make sense as exact substrings; fuzzy matching them
+// mostly produces noise.
+fn is_fuzzy(field: SearchField) -> bool {
+    !matches!(
+        field,
+        SearchField::Executable | SearchField::WmClass | SearchField::DesktopId
+    )
+}
+
+fn match_fields(app: &AppEntry, query_lower: &str, matcher: &SkimMatcherV2) -> Option<i64> {
+    let substring_score = app
+        .search_terms
+        .iter()
+        .filter(|term| term.text.contains(query_lower))
+        .map(|term| field_weight(term.field))
+        .max();
+
+    if substring_score.is_some() {
+        return substring_score;
+    }
+
+    app.search_terms
+        .iter()
+        .filter(|term| is_fuzzy(term.field))
+        .filter_map(|term| matcher.fuzzy_match(&term.text, query_lower))
+        .max()
 }
 
 fn should_exclude_web_search(query: &str) -> bool {
@@ -81,43 +137,49 @@ pub async fn search_applications(
     let calculator_enabled = config.calculator.enabled;
     let web_search_config = config.web_search.clone();
     let show_actions = config.window.show_actions;
+    let ranking = config.ranking.clone();
 
     let current_gen = SEARCH_GENERATION.fetch_add(1, Ordering::SeqCst);
 
     tokio::task::spawn_blocking(move || {
         let cache = APP_CACHE.blocking_read();
+        let binaries = BINARY_INDEX.blocking_read();
+        let app_executables = APP_EXECUTABLES.blocking_read();
+        let history = history::load();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
 
         if SEARCH_GENERATION.load(Ordering::SeqCst) != current_gen + 1 {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Search superseded",
-            ));
+            return Err(std::io::Error::other("Search superseded"));
         }
 
         let results = match query.chars().next() {
             None => {
-                let history = load_history();
                 let mut heatmap_results = Vec::new();
                 let mut alphabetical_results = Vec::new();
 
                 for app in cache.values() {
                     if app.path.ends_with(".desktop") {
-                        let result = SearchResult {
-                            score: calculate_bonus_score(app),
+                        let mut result = SearchResult {
+                            score: calculate_bonus_score(app, &history.entries, &ranking, now),
                             app: app.clone(),
                         };
 
-                        if history.contains_key(&app.name) {
-                            heatmap_results.push(result);
-                        } else {
-                            alphabetical_results.push(result);
+                        let key = app.history_key();
+                        match key.and_then(|key| history.entries.get(&key)) {
+                            Some(entry) => {
+                                result.score += history::time_context(entry, now, &ranking);
+                                heatmap_results.push(result);
+                            }
+                            None => alphabetical_results.push(result),
                         }
                     }
                 }
 
                 heatmap_results.sort_unstable_by_key(|item| -item.score);
-                alphabetical_results
-                    .sort_by(|a, b| a.app.name.to_lowercase().cmp(&b.app.name.to_lowercase()));
+                alphabetical_results.sort_by_key(|a| a.app.name.to_lowercase());
 
                 let mut results = heatmap_results;
                 results.extend(alphabetical_results);
@@ -130,58 +192,15 @@ pub async fn search_applications(
                 let mut seen_names = std::collections::HashSet::new();
 
                 for app in cache.values() {
-                    let name_lower = app.name.to_lowercase();
-                    let name_key = name_lower.clone();
-                    let mut added = false;
+                    let name_key = app.name.to_lowercase();
 
-                    if app.name.contains(&query) || name_lower.contains(&query_lower) {
+                    if let Some(score) = match_fields(app, &query_lower, &matcher) {
                         results.push(SearchResult {
                             app: app.clone(),
-                            score: BONUS_SCORE_BINARY + calculate_bonus_score(app),
+                            score: score
+                                + calculate_bonus_score(app, &history.entries, &ranking, now),
                         });
-                        seen_names.insert(name_key.clone());
-                        added = true;
-                    }
-
-                    if app
-                        .keywords
-                        .iter()
-                        .any(|k| k.contains(&query) || k.to_lowercase().contains(&query_lower))
-                        && !added
-                    {
-                        results.push(SearchResult {
-                            app: app.clone(),
-                            score: BONUS_SCORE_KEYWORD_MATCH + calculate_bonus_score(app),
-                        });
-                        seen_names.insert(name_key.clone());
-                        added = true;
-                    }
-
-                    if app
-                        .categories
-                        .iter()
-                        .any(|c| c.contains(&query) || c.to_lowercase().contains(&query_lower))
-                        && !added
-                    {
-                        results.push(SearchResult {
-                            app: app.clone(),
-                            score: BONUS_SCORE_CATEGORY_MATCH + calculate_bonus_score(app),
-                        });
-                        seen_names.insert(name_key.clone());
-                        added = true;
-                    }
-
-                    if let Some(score) = matcher
-                        .fuzzy_match(&app.name, &query)
-                        .or_else(|| matcher.fuzzy_match(&name_lower, &query_lower))
-                    {
-                        if !added {
-                            results.push(SearchResult {
-                                app: app.clone(),
-                                score: score + calculate_bonus_score(app),
-                            });
-                            seen_names.insert(name_key.clone());
-                        }
+                        seen_names.insert(name_key);
                     }
 
                     if show_actions {
@@ -189,6 +208,9 @@ pub async fn search_applications(
                             let mut action_app = app.clone();
                             action_app.name = format!("{} - {}", app.name, action.name);
                             action_app.exec = action.exec.clone();
+                            action_app.activation = Activation::Spawn(action.exec_args.clone());
+                            action_app.action_id = Some(action.id.clone());
+                            action_app.entry_type = EntryType::Action;
                             if let Some(icon) = &action.icon_name {
                                 action_app.icon_name = icon.clone();
                             }
@@ -198,49 +220,38 @@ pub async fn search_applications(
                                 || action_name.contains(&query_lower)
                                 || matcher.fuzzy_match(&action_name, &query).is_some()
                             {
+                                let score = calculate_bonus_score(
+                                    &action_app,
+                                    &history.entries,
+                                    &ranking,
+                                    now,
+                                );
                                 results.push(SearchResult {
                                     app: action_app,
-                                    score: calculate_bonus_score(app) - 100,
+                                    score: score - 100,
                                 });
                             }
                         }
                     }
-
-                    if !added {
-                        for keyword in &app.keywords {
-                            if let Some(score) =
-                                matcher.fuzzy_match(&keyword.to_lowercase(), &query)
-                            {
-                                results.push(SearchResult {
-                                    app: app.clone(),
-                                    score: score + calculate_bonus_score(app),
-                                });
-                                seen_names.insert(name_key.clone());
-                                break;
-                            }
-                        }
-
-                        for category in &app.categories {
-                            if let Some(score) =
-                                matcher.fuzzy_match(&category.to_lowercase(), &query)
-                            {
-                                results.push(SearchResult {
-                                    app: app.clone(),
-                                    score: score + calculate_bonus_score(app),
-                                });
-                                seen_names.insert(name_key.clone());
-                                break;
-                            }
-                        }
-                    }
                 }
 
-                if !seen_names.contains(&query_lower) {
-                    if let Some(result) = check_binary(&query) {
+                if !seen_names.contains(&query_lower) && !app_executables.contains(query.trim()) {
+                    if let Some(result) = check_binary(&query, &binaries) {
                         results.push(result);
                     }
                 }
 
+                results.extend(search_binaries(
+                    &query,
+                    &binaries,
+                    &app_executables,
+                    &matcher,
+                ));
+
+                if let Some(result) = check_path(&query) {
+                    results.push(result);
+                }
+
                 if results.is_empty()
                     && calculator_enabled
                     && query.trim().chars().next().unwrap().is_ascii_digit()
@@ -252,6 +263,14 @@ pub async fn search_applications(
                     results.push(create_web_search_entry(&query, &web_search_config));
                 }
 
+                let query_shares = history.query_shares(&query);
+                for result in &mut results {
+                    let key = result.app.history_key();
+                    if let Some(share) = key.and_then(|key| query_shares.get(key.as_str())) {
+                        result.score += (share * ranking.query_weight).round() as i64;
+                    }
+                }
+
                 results.sort_unstable_by_key(|item| -item.score);
                 if results.len() > max_results {
                     results.truncate(max_results);
@@ -262,38 +281,28 @@ pub async fn search_applications(
         };
 
         if SEARCH_GENERATION.load(Ordering::SeqCst) != current_gen + 1 {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Search superseded",
-            ));
+            return Err(std::io::Error::other("Search superseded"));
         }
 
         tx.send(results)
-            .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "Failed to send results"))
+            .map_err(|_| std::io::Error::other("Failed to send results"))
     });
 
     rx.await
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "Failed to receive results"))
+        .map_err(|_| std::io::Error::other("Failed to receive results"))
 }
 
 #[inline(always)]
-fn calculate_bonus_score(app: &AppEntry) -> i64 {
-    let mut score = 0;
-    let history = load_history();
-
-    if let Some(entry) = history.get(&app.name) {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-
-        let seconds_since_used = (now - entry.last_used) as i64;
-        score = 10000 - (seconds_since_used / 10);
-
-        score += (entry.use_count * 20).min(200);
-    } else {
-        score += (app.launch_count as i64 * 20).min(200);
-    }
+fn calculate_bonus_score(
+    app: &AppEntry,
+    history: &HashMap<String, HeatmapEntry>,
+    ranking: &Ranking,
+    now: u64,
+) -> i64 {
+    let mut score = app
+        .history_key()
+        .and_then(|key| history.get(&key))
+        .map_or(0, |entry| history::frecency(entry, now, ranking));
 
     if app.icon_name != "application-x-executable" {
         score += BONUS_SCORE_ICON_NAME;
@@ -310,43 +319,137 @@ fn calculate_bonus_score(app: &AppEntry) -> i64 {
 }
 
 #[inline(always)]
-fn check_binary(query: &str) -> Option<SearchResult> {
-    let parts: Vec<&str> = query.split_whitespace().collect();
-    if parts.is_empty() {
-        return None;
+fn create_binary_entry(
+    name: String,
+    binary: &PathBinary,
+    activation: Activation,
+    score: i64,
+) -> SearchResult {
+    let entry_type = match activation {
+        Activation::Shell(_) => EntryType::Command,
+        _ => EntryType::Binary,
+    };
+
+    SearchResult {
+        app: AppEntry {
+            path: binary.path.to_string_lossy().into_owned(),
+            icon_name: String::from("application-x-executable"),
+            score_boost: score,
+            executable: binary.name.clone(),
+            ..AppEntry::new(name, entry_type, activation)
+        },
+        score,
     }
+}
 
-    let bin_path = format!("/usr/bin/{}", parts[0]);
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-
-    std::fs::metadata(&bin_path)
-        .ok()
-        .filter(|metadata| metadata.permissions().mode() & 0o111 != 0)
-        .map(|_| SearchResult {
-            app: AppEntry {
-                name: query.to_string(),
-                description: String::new(),
-                path: bin_path.clone(),
-                exec: if parts.len() > 1 {
-                    format!("{} {}", bin_path, parts[1..].join(" "))
-                } else {
-                    bin_path
-                },
-                icon_name: String::from("application-x-executable"),
-                launch_count: 0,
-                last_used: Some(now),
-                entry_type: EntryType::Application,
-                score_boost: BONUS_SCORE_BINARY,
-                keywords: Vec::new(),
-                categories: Vec::new(),
-                terminal: false,
-                actions: Vec::new(),
-            },
-            score: BONUS_SCORE_BINARY,
+fn check_binary(query: &str, binaries: &[PathBinary]) -> Option<SearchResult> {
+    let program = query.split_whitespace().next()?;
+    let binary = binaries::find_binary(binaries, program)?;
+    let bin_path = binary.path.to_string_lossy();
+
+    // Only typed commands that use shell syntax go through sh, everything
+    // else is spawned directly with its arguments.
+    let activation = if query.contains(SHELL_METACHARACTERS) {
+        Activation::Shell(query.replacen(program, &bin_path, 1))
+    } else {
+        match shlex::split(query) {
+            Some(args) => Activation::Spawn(
+                std::iter::once(bin_path.into_owned())
+                    .chain(args.into_iter().skip(1))
+                    .map(ExecArg::Literal)
+                    .collect(),
+            ),
+            None => Activation::Shell(query.replacen(program, &bin_path, 1)),
+        }
+    };
+
+    Some(create_binary_entry(
+        query.to_string(),
+        binary,
+        activation,
+        BONUS_SCORE_BINARY,
+    ))
+}
+
+// Binaries are ranked among themselves by how well they match, then placed
+// below desktop entries and capped, so a large $PATH can't crowd
+// applications out of the list.
+fn search_binaries(
+    query: &str,
+    binaries: &[PathBinary],
+    app_executables: &HashSet<String>,
+    matcher: &SkimMatcherV2,
+) -> Vec<SearchResult> {
+    if query.contains(char::is_whitespace) {
+        return Vec::new();
+    }
+
+    let mut matches: Vec<(i64, usize)> = binaries
+        .iter()
+        .enumerate()
+        .filter(|(_, binary)| binary.name != query && !app_executables.contains(&binary.name))
+        .filter_map(|(position, binary)| {
+            let score = matcher.fuzzy_match(&binary.name, query)?;
+            let prefix_bonus = if binary.name.starts_with(query) {
+                BONUS_SCORE_PATH_BINARY
+            } else {
+                0
+            };
+            Some((score + prefix_bonus, position))
         })
+        .collect();
+
+    matches.sort_by_key(|&(score, position)| (-score, position));
+    matches
+        .into_iter()
+        .take(MAX_PATH_BINARY_RESULTS)
+        .enumerate()
+        .map(|(rank, (_, position))| {
+            let binary = &binaries[position];
+            let activation =
+                Activation::Spawn(vec![ExecArg::Literal(binary.path.to_string_lossy().into())]);
+            create_binary_entry(
+                binary.name.clone(),
+                binary,
+                activation,
+                BONUS_SCORE_PATH_BINARY - rank as i64,
+            )
+        })
+        .collect()
+}
+
+fn check_path(query: &str) -> Option<SearchResult> {
+    let query = query.trim();
+    if !query.starts_with(['/', '~']) {
+        return None;
+    }
+
+    let path = PathBuf::from(shellexpand::tilde(query).to_string());
+    let metadata = std::fs::metadata(&path).ok()?;
+    let uri = format!(
+        "file://{}",
+        utf8_percent_encode(&path.to_string_lossy(), FILE_URI_ENCODE_SET)
+    );
+    let name = path.to_string_lossy().into_owned();
+
+    Some(SearchResult {
+        app: AppEntry {
+            description: String::from(if metadata.is_dir() {
+                "Open folder"
+            } else {
+                "Open file"
+            }),
+            path: name.clone(),
+            icon_name: String::from(if metadata.is_dir() {
+                "folder"
+            } else {
+                "text-x-generic"
+            }),
+            score_boost: BONUS_SCORE_FILE,
+            ..AppEntry::new(name, EntryType::File, Activation::OpenUri(uri))
+        },
+        score: BONUS_SCORE_FILE,
+    })
 }
 
 pub async fn search_dmenu(
@@ -388,114 +491,65 @@ pub async fn search_dmenu(
 
         let results = results.into_iter().map(|(line, _)| line).collect();
         tx.send(results)
-            .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "Failed to send results"))
+            .map_err(|_| std::io::Error::other("Failed to send results"))
     });
 
     rx.await
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "Failed to receive results"))
+        .map_err(|_| std::io::Error::other("Failed to receive results"))
 }
 
 fn create_web_search_entry(query: &str, config: &WebSearch) -> SearchResult {
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-
     if let Some(colon_pos) = query.find(':') {
         let (prefix, search_term) = query.split_at(colon_pos);
         let search_term = &search_term[1..];
 
         if let Some(prefix_config) = config.prefixes.iter().find(|p| p.prefix == prefix) {
+            let activation = Activation::OpenUri(format!(
+                "{}{}",
+                prefix_config.url,
+                utf8_percent_encode(search_term, NON_ALPHANUMERIC)
+            ));
             return SearchResult {
                 app: AppEntry {
-                    name: format!("Search '{}' on {}", search_term, prefix),
                     description: String::from("Open in default web browser"),
-                    path: String::new(),
-                    exec: format!(
-                        "xdg-open \"{}{}\"",
-                        prefix_config.url,
-                        utf8_percent_encode(search_term, NON_ALPHANUMERIC)
-                    ),
-                    icon_name: String::from("web-browser"),
-                    launch_count: 0,
-                    last_used: Some(now),
-                    entry_type: EntryType::Application,
-                    score_boost: 0,
-                    keywords: Vec::new(),
-                    categories: vec![String::from("Web Search")],
-                    terminal: false,
-                    actions: Vec::new(),
+                    ..AppEntry::new(
+                        format!("Search '{}' on {}", search_term, prefix),
+                        EntryType::WebSearch,
+                        activation,
+                    )
                 },
                 score: BONUS_SCORE_WEB_SEARCH,
             };
         }
     }
 
+    let activation = Activation::OpenUri(format!(
+        "{}{}",
+        config.engine.get_url(),
+        utf8_percent_encode(query, NON_ALPHANUMERIC)
+    ));
+
     SearchResult {
         app: AppEntry {
-            name: format!("Search '{}' on the web", query),
             description: String::from("Open in default web browser"),
-            path: String::new(),
-            exec: format!(
-                "xdg-open \"{}{}\"",
-                config.engine.get_url(),
-                utf8_percent_encode(query, NON_ALPHANUMERIC)
-            ),
-            icon_name: String::from("web-browser"),
-            launch_count: 0,
-            last_used: Some(now),
-            entry_type: EntryType::Application,
-            score_boost: 0,
-            keywords: Vec::new(),
-            categories: vec![String::from("Web Search")],
-            terminal: false,
-            actions: Vec::new(),
+            ..AppEntry::new(
+                format!("Search '{}' on the web", query),
+                EntryType::WebSearch,
+                activation,
+            )
         },
         score: BONUS_SCORE_WEB_SEARCH,
     }
 }
 
-fn load_history() -> HashMap<String, HistoryEntry> {
-    let mut history = HashMap::new();
-
-    if let Ok(heatmap) = launcher::load_heatmap() {
-        for (name, entry) in heatmap {
-            history.insert(
-                name,
-                HistoryEntry {
-                    last_used: entry.last_used,
-                    use_count: entry.count.into(),
-                },
-            );
-        }
-    }
-
-    history
-}
-
 fn create_calc_entry(query: &str) -> SearchResult {
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-
     let res = handle_calculation(query);
+    let activation = Activation::CopyText(res.clone());
 
     SearchResult {
         app: AppEntry {
-            name: res.clone(),
             description: String::from("Copy to clipboard"),
-            path: String::new(),
-            exec: format!("wl-copy -t text/plain \"{}\"", res),
-            icon_name: String::from("accessories-calculator"),
-            launch_count: 0,
-            last_used: Some(now),
-            entry_type: EntryType::Application,
-            score_boost: 0,
-            keywords: Vec::new(),
-            categories: vec![String::from("Calculation")],
-            terminal: false,
-            actions: Vec::new(),
+            ..AppEntry::new(res, EntryType::Calculation, activation)
         },
         score: BONUS_SCORE_CALC,
     }
@@ -0,0 +1,214 @@
+use crate::{config, exec, log};
+use std::{env, fs, path::PathBuf};
+
+struct KnownTerminal {
+    binary: &'static str,
+    desktop_id: &'static str,
+    args: &'static [&'static str],
+}
+
+const KNOWN_TERMINALS: &[KnownTerminal] = &[
+    KnownTerminal {
+        binary: "foot",
+        desktop_id: "foot.desktop",
+        args: &[],
+    },
+    KnownTerminal {
+        binary: "kitty",
+        desktop_id: "kitty.desktop",
+        args: &[],
+    },
+    KnownTerminal {
+        binary: "alacritty",
+        desktop_id: "Alacritty.desktop",
+        args: &["-e"],
+    },
+    KnownTerminal {
+        binary: "wezterm",
+        desktop_id: "org.wezfurlong.wezterm.desktop",
+        args: &["start", "--"],
+    },
+    KnownTerminal {
+        binary: "ghostty",
+        desktop_id: "com.mitchellh.ghostty.desktop",
+        args: &["-e"],
+    },
+    KnownTerminal {
+        binary: "rio",
+        desktop_id: "rio.desktop",
+        args: &["-e"],
+    },
+    KnownTerminal {
+        binary: "konsole",
+        desktop_id: "org.kde.konsole.desktop",
+        args: &["-e"],
+    },
+    KnownTerminal {
+        binary: "ptyxis",
+        desktop_id: "org.gnome.Ptyxis.desktop",
+        args: &["--"],
+    },
+    KnownTerminal {
+        binary: "kgx",
+        desktop_id: "org.gnome.Console.desktop",
+        args: &["--"],
+    },
+    KnownTerminal {
+        binary: "gnome-terminal",
+        desktop_id: "org.gnome.Terminal.desktop",
+        args: &["--"],
+    },
+    KnownTerminal {
+        binary: "xfce4-terminal",
+        desktop_id: "xfce4-terminal.desktop",
+        args: &["-x"],
+    },
+    KnownTerminal {
+        binary: "mate-terminal",
+        desktop_id: "mate-terminal.desktop",
+        args: &["-x"],
+    },
+    KnownTerminal {
+        binary: "terminator",
+        desktop_id: "terminator.desktop",
+        args: &["-x"],
+    },
+    KnownTerminal {
+        binary: "lxterminal",
+        desktop_id: "lxterminal.desktop",
+        args: &["-e"],
+    },
+    KnownTerminal {
+        binary: "qterminal",
+        desktop_id: "qterminal.desktop",
+        args: &["-e"],
+    },
+    KnownTerminal {
+        binary: "st",
+        desktop_id: "st.desktop",
+        args: &["-e"],
+    },
+    KnownTerminal {
+        binary: "urxvt",
+        desktop_id: "rxvt-unicode.desktop",
+        args: &["-e"],
+    },
+    KnownTerminal {
+        binary: "xterm",
+        desktop_id: "xterm.desktop",
+        args: &["-e"],
+    },
+];
+
+fn known_command(terminal: &KnownTerminal, argv: Vec<String>) -> Option<Vec<String>> {
+    let path = exec::find_executable(terminal.binary)?;
+    Some(
+        [path.to_string_lossy().into_owned()]
+            .into_iter()
+            .chain(terminal.args.iter().map(|arg| arg.to_string()))
+            .chain(argv)
+            .collect(),
+    )
+}
+
+fn get_config_dirs() -> Vec<PathBuf> {
+    let config_home = config::xdg_dir("XDG_CONFIG_HOME", "~/.config");
+    let config_dirs = env::var("XDG_CONFIG_DIRS").unwrap_or_else(|_| String::from("/etc/xdg"));
+
+    std::iter::once(config_home)
+        .chain(
+            config_dirs
+                .split(':')
+                .filter(|dir| dir.starts_with('/'))
+                .map(PathBuf::from),
+        )
+        .collect()
+}
+
+fn preferred_terminals() -> Vec<String> {
+    let desktops: Vec<String> = env::var("XDG_CURRENT_DESKTOP")
+        .unwrap_or_default()
+        .split(':')
+        .filter(|desktop| !desktop.is_empty())
+        .map(|desktop| desktop.to_lowercase())
+        .collect();
+
+    let mut ids = Vec::new();
+    for dir in get_config_dirs() {
+        let lists = desktops
+            .iter()
+            .map(|desktop| format!("{}-xdg-terminals.list", desktop))
+            .chain(Some(String::from("xdg-terminals.list")));
+
+        for list in lists {
+            let Ok(contents) = fs::read_to_string(dir.join(&list)) else {
+                continue;
+            };
+            ids.extend(
+                contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with(['#', '-']))
+                    .map(|line| line.trim_start_matches('+'))
+                    .map(|line| line.split(':').next().unwrap_or(line).to_string()),
+            );
+        }
+    }
+
+    ids
+}
+
+pub fn wrap_in_terminal(argv: Vec<String>, template: &str) -> Vec<String> {
+    if !template.trim().is_empty() {
+        return exec::wrap_command(template, argv);
+    }
+
+    if let Some(path) = exec::find_executable("xdg-terminal-exec") {
+        return [path.to_string_lossy().into_owned()]
+            .into_iter()
+            .chain(argv)
+            .collect();
+    }
+
+    for id in preferred_terminals() {
+        let Some(terminal) = KNOWN_TERMINALS.iter().find(|t| t.desktop_id == id) else {
+            log!("Skipping unknown terminal {} from xdg-terminals.list", id);
+            continue;
+        };
+        if let Some(command) = known_command(terminal, argv.clone()) {
+            return command;
+        }
+    }
+
+    if let Ok(terminal) = env::var("TERMINAL").map(|t| t.trim().to_string()) {
+        let name = terminal.rsplit('/').next().unwrap_or(&terminal);
+        match KNOWN_TERMINALS.iter().find(|t| t.binary == name) {
+            Some(known) => {
+                let args = known.args.iter().map(|arg| arg.to_string());
+                return [terminal.clone()]
+                    .into_iter()
+                    .chain(args)
+                    .chain(argv)
+                    .collect();
+            }
+            None if !terminal.is_empty() => {
+                return [terminal, String::from("-e")]
+                    .into_iter()
+                    .chain(argv)
+                    .collect();
+            }
+            None => {}
+        }
+    }
+
+    KNOWN_TERMINALS
+        .iter()
+        .find_map(|terminal| known_command(terminal, argv.clone()))
+        .unwrap_or_else(|| {
+            log!("No terminal emulator found, falling back to xterm");
+            [String::from("xterm"), String::from("-e")]
+                .into_iter()
+                .chain(argv)
+                .collect()
+        })
+}
@@ -1,13 +1,14 @@
 use crate::{
-    config::{Config, ConfigError, WindowAnchor},
-    launcher::{self, AppEntry, EntryType, APP_CACHE},
+    config::{Config, ConfigError, Launch, WindowAnchor},
+    dbus,
+    exec::{self, Activation, LaunchFailure, LaunchedProcess},
+    launcher::{self, AppEntry, EntryType},
     log,
     search::{self, SearchResult},
 };
 use gtk4::{
-    gdk::Key,
-    gio,
-    glib::{self},
+    gdk::{self, Key},
+    gio, glib,
     prelude::*,
     subclass::prelude::*,
     Application, ApplicationWindow, Box as GtkBox, CssProvider, Label, ListView, Orientation,
@@ -16,13 +17,19 @@ use gtk4::{
 };
 use gtk4_layer_shell::{Edge, KeyboardMode, Layer, LayerShell};
 use std::{
-    cell::RefCell,
-    process::Command,
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    os::unix::process::ExitStatusExt,
+    process::ExitStatus,
     rc::Rc,
-    time::{SystemTime, UNIX_EPOCH},
 };
 use tokio::runtime::Handle;
 
+const LAUNCH_ERROR_WIDGET: &str = "launch-error";
+const CONFIG_ERROR_WIDGET: &str = "config-error";
+
+type EntryField = fn(&AppEntry) -> &str;
+
 pub struct LauncherWindow {
     window: ApplicationWindow,
     search_entry: SearchEntry,
@@ -154,9 +161,17 @@ impl LauncherWindow {
             let config = Config::load();
             if let Some(app_entry) = list_item.item().and_downcast::<AppEntryObject>() {
                 if let Some(box_row) = list_item.child().and_downcast::<GtkBox>() {
+                    let entry_type = app_entry.imp().app_entry().entry_type;
+                    for kind in EntryType::ALL {
+                        box_row.remove_css_class(kind.css_class());
+                    }
+                    box_row.add_css_class(entry_type.css_class());
+
                     if config.window.show_icons {
                         if let Some(icon) = box_row.first_child().and_downcast::<gtk4::Image>() {
-                            let icon_name = app_entry.imp().icon_name();
+                            let icon_name = Some(app_entry.imp().icon_name())
+                                .filter(|name| !name.is_empty())
+                                .unwrap_or(entry_type.default_icon());
                             if icon_name.starts_with('/') {
                                 icon.set_from_file(Some(icon_name));
                             } else {
@@ -174,10 +189,20 @@ impl LauncherWindow {
                         .first_child()
                         .and_downcast::<Label>()
                         .expect("First child must be a Label");
-                    name_label.set_text(app_entry.imp().name());
+                    match entry_type {
+                        EntryType::Calculation => {
+                            name_label.set_text(&format!("= {}", app_entry.imp().name()))
+                        }
+                        _ => name_label.set_text(app_entry.imp().name()),
+                    }
 
                     if config.window.show_descriptions {
-                        let desc = app_entry.imp().description();
+                        let desc = match entry_type {
+                            EntryType::Binary | EntryType::Command => {
+                                &app_entry.imp().app_entry().exec
+                            }
+                            _ => app_entry.imp().description(),
+                        };
                         if !desc.is_empty() {
                             if let Some(desc_label) = text_box
                                 .first_child()
@@ -260,10 +285,7 @@ impl LauncherWindow {
         let app_data_store = Rc::new(RefCell::new(Vec::with_capacity(50)));
         update_results_list(&list_view, results, &app_data_store);
 
-        if let Some(error) = error {
-            let error_overlay = create_error_overlay(&error);
-            main_box.prepend(&error_overlay);
-        }
+        show_config_error(&main_box, error.as_ref());
 
         let launcher = Self {
             window,
@@ -274,6 +296,7 @@ impl LauncherWindow {
         };
 
         launcher.setup_signals();
+        launcher.setup_copy_actions(app);
         launcher
     }
 
@@ -353,28 +376,8 @@ impl LauncherWindow {
         let app_data_store = Rc::new(RefCell::new(Vec::new()));
         let initial_entries: Vec<_> = entries
             .into_iter()
-            .map(|entry| AppEntry {
-                name: entry.clone(),
-                description: String::new(),
-                path: String::new(),
-                exec: entry,
-                icon_name: String::new(),
-                launch_count: 0,
-                last_used: Some(
-                    SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs(),
-                ),
-                entry_type: EntryType::Application,
-                score_boost: 0,
-                keywords: Vec::new(),
-                categories: Vec::new(),
-                terminal: false,
-                actions: Vec::new(),
-            })
             .map(|entry| SearchResult {
-                app: entry,
+                app: dmenu_entry(entry),
                 score: 0,
             })
             .collect();
@@ -441,6 +444,29 @@ impl LauncherWindow {
         window.set_margin(Edge::Right, config.window.margin_right);
     }
 
+    fn setup_copy_actions(&self, app: &Application) {
+        let copy_actions: [(&str, &str, EntryField); 3] = [
+            ("copy-name", "<Alt>n", |entry| &entry.name),
+            ("copy-exec", "<Alt>e", |entry| &entry.exec),
+            ("copy-path", "<Alt>p", |entry| &entry.path),
+        ];
+
+        for (name, accel, field) in copy_actions {
+            let action = gio::SimpleAction::new(name, None);
+            let list_view = self.list_view.clone();
+            action.connect_activate(move |_, _| {
+                if let Some(selected) = get_selected_item(&list_view) {
+                    let text = field(selected.imp().app_entry());
+                    if !text.is_empty() {
+                        copy_to_clipboard(&list_view, text);
+                    }
+                }
+            });
+            self.window.add_action(&action);
+            app.set_accels_for_action(&format!("win.{}", name), &[accel]);
+        }
+    }
+
     fn setup_signals(&self) {
         let config = Config::load();
 
@@ -567,7 +593,11 @@ impl LauncherWindow {
             if let Some(model) = list_view.model() {
                 if let Some(item) = model.item(position) {
                     if let Some(app_entry) = item.downcast_ref::<AppEntryObject>() {
-                        if launch_application(app_entry.imp().app_entry(), &search_entry_for_row) {
+                        if launch_application(
+                            app_entry.imp().app_entry(),
+                            &search_entry_for_row,
+                            &window_for_row,
+                        ) {
                             window_for_row.hide();
                         }
                     }
@@ -582,7 +612,11 @@ impl LauncherWindow {
         self.search_entry.connect_activate(move |_| {
             if let Some(selected) = get_selected_item(&list_view_for_activate) {
                 if let Some(app_entry) = selected.downcast_ref::<AppEntryObject>() {
-                    if launch_application(app_entry.imp().app_entry(), &search_entry_for_activate) {
+                    if launch_application(
+                        app_entry.imp().app_entry(),
+                        &search_entry_for_activate,
+                        &window_for_activate,
+                    ) {
                         window_for_activate.hide();
                     }
                 }
@@ -590,7 +624,10 @@ impl LauncherWindow {
         });
 
         let search_entry_for_hide = self.search_entry.clone();
-        self.window.connect_hide(move |_| {
+        self.window.connect_hide(move |window| {
+            if let Some(main_box) = window.child().and_downcast::<GtkBox>() {
+                clear_launch_error(&main_box);
+            }
             search_entry_for_hide.set_text("");
             search_entry_for_hide.grab_focus();
         });
@@ -689,26 +726,7 @@ impl LauncherWindow {
                 let search_results: Vec<SearchResult> = results
                     .into_iter()
                     .map(|name| SearchResult {
-                        app: AppEntry {
-                            name: name.clone(),
-                            description: String::new(),
-                            path: String::new(),
-                            exec: name,
-                            icon_name: String::new(),
-                            launch_count: 0,
-                            last_used: Some(
-                                SystemTime::now()
-                                    .duration_since(UNIX_EPOCH)
-                                    .unwrap()
-                                    .as_secs(),
-                            ),
-                            entry_type: EntryType::Application,
-                            score_boost: 0,
-                            keywords: Vec::new(),
-                            categories: Vec::new(),
-                            terminal: false,
-                            actions: Vec::new(),
-                        },
+                        app: dmenu_entry(name),
                         score: 0,
                     })
                     .collect();
@@ -851,36 +869,229 @@ fn select_previous(list_view: &ListView) {
     }
 }
 
-fn launch_application(app: &AppEntry, search_entry: &gtk4::SearchEntry) -> bool {
-    let mut success = false;
+fn startup_notify_id(app: &AppEntry, launch_context: &gdk::AppLaunchContext) -> Option<String> {
+    if !app.startup_notify {
+        return None;
+    }
+
+    let app_info = Some(&app.path)
+        .filter(|path| path.ends_with(".desktop"))
+        .and_then(gio::DesktopAppInfo::from_filename);
+
+    launch_context
+        .startup_notify_id(app_info.as_ref(), &[])
+        .map(String::from)
+}
+
+// The clipboard content is served by this process, which keeps running in the
+// background after the window hides.
+// Dmenu rows are only printed back to the caller, never launched.
+fn dmenu_entry(line: String) -> AppEntry {
+    AppEntry {
+        exec: line.clone(),
+        icon_name: String::new(),
+        ..AppEntry::new(line, EntryType::Application, Activation::Spawn(Vec::new()))
+    }
+}
+
+fn copy_to_clipboard(widget: &impl IsA<gtk4::Widget>, text: &str) {
+    log!("Copying {} characters to clipboard", text.chars().count());
+    widget.display().clipboard().set_text(text);
+}
+
+fn finish_launch(
+    app: &AppEntry,
+    query: &str,
+    window: &ApplicationWindow,
+    search_entry: &SearchEntry,
+) {
+    let max_queries = Config::load().ranking.query_history_size;
+    launcher::increment_launch_count(app, query, max_queries);
+    if !window.is_visible() {
+        search_entry.set_text("__refresh__");
+        search_entry.set_text("");
+    }
+}
 
-    if let Ok(new_count) = launcher::increment_launch_count(app) {
-        let app_name = app.name.clone();
-        let mut cache = APP_CACHE.blocking_write();
-        if let Some(cached_app) = cache.get_mut(&app_name) {
-            cached_app.launch_count = new_count;
+// The child watch stays registered until the process exits, so every
+// launched application is reaped, but failures are only reported while the
+// launch is still within its grace period.
+fn watch_launch(
+    app: &AppEntry,
+    process: LaunchedProcess,
+    startup_id: Option<String>,
+    launch_context: gdk::AppLaunchContext,
+    window: &ApplicationWindow,
+    search_entry: &SearchEntry,
+    query: &str,
+) {
+    let settled = Rc::new(Cell::new(false));
+
+    glib::timeout_add_local_once(exec::LAUNCH_GRACE_PERIOD, {
+        let settled = settled.clone();
+        let app = app.clone();
+        let query = query.to_string();
+        let window = window.clone();
+        let search_entry = search_entry.clone();
+        move || {
+            if !settled.replace(true) {
+                finish_launch(&app, &query, &window, &search_entry);
+            }
+        }
+    });
+
+    let app = app.clone();
+    let query = query.to_string();
+    let window = window.clone();
+    let search_entry = search_entry.clone();
+    glib::child_watch_add_local(glib::Pid(process.pid as i32), move |_, status| {
+        let status = ExitStatus::from_raw(status);
+        log!("{} (pid {}) exited with {}", app.name, process.pid, status);
+        if settled.replace(true) {
+            return;
         }
 
-        log!("Launching application: {}", app.name);
-        if app.terminal {
-            let terminal = std::env::var("TERMINAL").unwrap_or_else(|_| "xterm".to_string());
-            success = Command::new(terminal)
-                .arg("-e")
-                .arg("sh")
-                .arg("-c")
-                .arg(&app.exec)
-                .spawn()
-                .is_ok()
-        } else {
-            success = Command::new("sh").arg("-c").arg(&app.exec).spawn().is_ok()
+        if status.success() {
+            finish_launch(&app, &query, &window, &search_entry);
+            return;
         }
-        if success {
-            search_entry.set_text("__refresh__");
-            search_entry.set_text("");
+
+        log!("Launching {} failed with {}", app.name, status);
+        if let Some(id) = &startup_id {
+            launch_context.launch_failed(id);
         }
+        let failure = LaunchFailure {
+            reason: status.to_string(),
+            stderr: process.stderr_tail(),
+        };
+        show_launch_error(&window, &app.name, &failure);
+    });
+}
+
+fn launch_application(
+    app: &AppEntry,
+    search_entry: &SearchEntry,
+    window: &ApplicationWindow,
+) -> bool {
+    log!("Launching application: {}", app.name);
+    let config = Config::load();
+    let query = search_entry.text().to_string();
+    let max_queries = config.ranking.query_history_size;
+    let launch_context = search_entry.display().app_launch_context();
+    let startup_id = startup_notify_id(app, &launch_context);
+
+    let is_application = matches!(app.entry_type, EntryType::Application | EntryType::Action);
+    if is_application && app.dbus_activatable {
+        let mut platform_data = HashMap::new();
+        if let Some(id) = &startup_id {
+            platform_data.insert(String::from("activation-token"), id.to_variant());
+            platform_data.insert(String::from("desktop-startup-id"), id.to_variant());
+        }
+
+        let app = app.clone();
+        let search_entry = search_entry.clone();
+        let window = window.clone();
+        let (desktop_id, action_id) = (app.desktop_id.clone(), app.action_id.clone());
+        dbus::activate(
+            &desktop_id,
+            action_id.as_deref(),
+            &[],
+            platform_data,
+            move |result| match result {
+                Ok(()) => {
+                    launcher::increment_launch_count(&app, &query, max_queries);
+                    if !window.is_visible() {
+                        search_entry.set_text("__refresh__");
+                        search_entry.set_text("");
+                    }
+                }
+                Err(e) => {
+                    log!(
+                        "D-Bus activation of {} failed ({}), falling back to Exec",
+                        app.desktop_id,
+                        e
+                    );
+                    // The window is already hidden, so a failed fallback has
+                    // to bring it back to be noticed.
+                    let spawned = spawn_application(
+                        &app,
+                        &search_entry,
+                        &window,
+                        &Config::load().launch,
+                        startup_id,
+                        launch_context,
+                        &query,
+                    );
+                    if !spawned {
+                        let failure = LaunchFailure {
+                            reason: e.message().to_string(),
+                            stderr: String::new(),
+                        };
+                        show_launch_error(&window, &app.name, &failure);
+                    }
+                }
+            },
+        );
+        return true;
+    }
+
+    if let Activation::CopyText(text) = &app.activation {
+        copy_to_clipboard(search_entry, text);
+        launcher::increment_launch_count(app, &query, max_queries);
+        return true;
     }
 
-    success
+    spawn_application(
+        app,
+        search_entry,
+        window,
+        &config.launch,
+        startup_id,
+        launch_context,
+        &query,
+    )
+}
+
+fn spawn_application(
+    app: &AppEntry,
+    search_entry: &SearchEntry,
+    window: &ApplicationWindow,
+    launch: &Launch,
+    startup_id: Option<String>,
+    launch_context: gdk::AppLaunchContext,
+    query: &str,
+) -> bool {
+    let process = match exec::build_command(app, launch) {
+        Some(launch_command) => launch_command
+            .spawn(startup_id.as_deref())
+            .map_err(|e| log!("Failed to launch {}: {}", app.name, e))
+            .ok(),
+        None => {
+            log!("Nothing to execute for {}", app.name);
+            None
+        }
+    };
+
+    match process {
+        Some(process) => {
+            watch_launch(
+                app,
+                process,
+                startup_id,
+                launch_context,
+                window,
+                search_entry,
+                query,
+            );
+            true
+        }
+        None => {
+            if let Some(id) = &startup_id {
+                launch_context.launch_failed(id);
+            }
+            false
+        }
+    }
 }
 
 trait WindowAnchoring {
@@ -995,9 +1206,10 @@ fn delete_word(text: &str, cursor_pos: usize) -> Option<(String, usize)> {
     Some((trimmed, new_pos))
 }
 
-pub fn create_error_overlay(error: &ConfigError) -> gtk4::Box {
+fn create_error_overlay(error: &ConfigError) -> gtk4::Box {
     let overlay_box = GtkBox::new(Orientation::Vertical, 8);
     overlay_box.add_css_class("error-overlay");
+    overlay_box.set_widget_name(CONFIG_ERROR_WIDGET);
 
     let error_label = Label::new(Some(&format!(
         "Config error at line {}: {}",
@@ -1013,3 +1225,59 @@ pub fn create_error_overlay(error: &ConfigError) -> gtk4::Box {
 
     overlay_box
 }
+
+fn create_launch_error_row(name: &str, failure: &LaunchFailure) -> gtk4::Box {
+    let error_box = GtkBox::new(Orientation::Vertical, 8);
+    error_box.add_css_class("error-overlay");
+    error_box.set_widget_name(LAUNCH_ERROR_WIDGET);
+
+    let error_label = Label::new(Some(&format!(
+        "Failed to launch {}: {}",
+        name, failure.reason
+    )));
+    error_label.add_css_class("error-message");
+    error_box.append(&error_label);
+
+    if !failure.stderr.is_empty() {
+        let stderr_label = Label::new(Some(&failure.stderr));
+        stderr_label.add_css_class("error-suggestion");
+        stderr_label.set_wrap(true);
+        stderr_label.set_xalign(0.0);
+        error_box.append(&stderr_label);
+    }
+
+    error_box
+}
+
+// Both error rows share the error-overlay styling, so they are told apart
+// by widget name.
+fn remove_error_rows(main_box: &GtkBox, widget_name: &str) {
+    let mut child = main_box.first_child();
+    while let Some(widget) = child {
+        child = widget.next_sibling();
+        if widget.widget_name() == widget_name {
+            main_box.remove(&widget);
+        }
+    }
+}
+
+fn clear_launch_error(main_box: &GtkBox) {
+    remove_error_rows(main_box, LAUNCH_ERROR_WIDGET);
+}
+
+pub fn show_config_error(main_box: &GtkBox, error: Option<&ConfigError>) {
+    remove_error_rows(main_box, CONFIG_ERROR_WIDGET);
+    if let Some(error) = error {
+        main_box.prepend(&create_error_overlay(error));
+    }
+}
+
+fn show_launch_error(window: &ApplicationWindow, name: &str, failure: &LaunchFailure) {
+    let Some(main_box) = window.child().and_downcast::<GtkBox>() else {
+        return;
+    };
+
+    clear_launch_error(&main_box);
+    main_box.prepend(&create_launch_error_row(name, failure));
+    window.present();
+}